@@ -0,0 +1,107 @@
+use crate::download::to_hex;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::path::{Path, PathBuf};
+
+/// A response kept on disk together with the ETag it can be revalidated with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub etag: String,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+/// On-disk cache of API responses, keyed by URL (which carries the ref) and
+/// `Accept` header. Entries are always revalidated with `If-None-Match`, so a
+/// stale entry is never served; a `304 Not Modified` just saves the download
+/// and does not count against the rate limit.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `~/.cache/gh-dir-rust` on Linux, or the platform's equivalent.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("gh-dir-rust"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, url: &str, accept: &str) -> PathBuf {
+        let digest = Sha1::digest(format!("{}\n{}", accept, url).as_bytes());
+        self.dir.join(format!("{}.json", to_hex(&digest)))
+    }
+
+    pub async fn get(&self, url: &str, accept: &str) -> Option<CachedResponse> {
+        let data = tokio::fs::read(self.entry_path(url, accept)).await.ok()?;
+        let entry: CachedResponse = serde_json::from_slice(&data).ok()?;
+        // Guards against the (unlikely) hash collision.
+        (entry.url == url).then_some(entry)
+    }
+
+    /// Stores an entry, writing through a temporary file so concurrent runs never
+    /// read a half-written one.
+    pub async fn put(&self, accept: &str, entry: &CachedResponse) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.entry_path(&entry.url, accept);
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        tokio::fs::write(&temp_path, serde_json::to_vec(entry)?).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    /// Deletes every entry and returns how many there were.
+    pub fn clear(&self) -> anyhow::Result<usize> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json" || ext == "tmp") {
+                std::fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip_and_clear() {
+        let dir = std::env::temp_dir().join(format!("gh-dir-cache-test-{}", std::process::id()));
+        let cache = ResponseCache::new(dir.clone());
+        let url = "https://api.github.com/repos/user/repo/contents/src?ref=main";
+        let entry = CachedResponse {
+            url: url.to_string(),
+            etag: "\"abc\"".to_string(),
+            content_type: Some("application/json".to_string()),
+            body: "[]".to_string(),
+        };
+
+        assert!(cache.get(url, "*/*").await.is_none());
+        cache.put("*/*", &entry).await.unwrap();
+        let cached = cache.get(url, "*/*").await.unwrap();
+        assert_eq!((cached.etag.as_str(), cached.body.as_str()), ("\"abc\"", "[]"));
+        // The same URL asked for in another representation is a different entry.
+        assert!(cache.get(url, "application/vnd.github.raw").await.is_none());
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.get(url, "*/*").await.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,94 +1,627 @@
-use crate::github::GitHubFile;
+use crate::github::{GitHubFile, RAW_MEDIA_TYPE};
+use crate::journal::Journal;
+use crate::lfs::{self, LfsClient, LfsObject};
 use futures_util::stream::{self, StreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
-use std::io::Write;
+use reqwest::header::{HeaderValue, ACCEPT, AUTHORIZATION, RANGE};
+use reqwest::{Client, StatusCode};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use zip::write::{FileOptions, ZipWriter};
 
-async fn download_file_with_retry(
-    client: Arc<Client>,
-    file: GitHubFile,
-    output_path: PathBuf,
-    pb: ProgressBar,
-) -> anyhow::Result<()> {
-    if let Some(download_url) = file.download_url {
-        let max_retries = 3;
-        let mut retry_count = 0;
-        
-        loop {
-            match client.get(&download_url).send().await {
-                Ok(response) => {
-                    if !response.status().is_success() {
-                        anyhow::bail!(
-                            "Failed to download file: {}\nStatus: {}\nURL: {}",
-                            file.path,
-                            response.status(),
-                            download_url
-                        );
+/// Raw URLs built from a tree listing carry no token, so private repositories
+/// need the same authorization header as the API.
+fn authorized_get(client: &Client, url: &str, token: &Option<String>) -> reqwest::RequestBuilder {
+    let request = client.get(url);
+    match token {
+        Some(token) => request.header(AUTHORIZATION, format!("token {}", token)),
+        None => request,
+    }
+}
+
+/// Downloads in flight at once, whatever `--concurrency` asks for.
+const MAX_CONCURRENT_DOWNLOADS: usize = 10;
+
+/// How long a download may go without receiving any data before it is retried.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lowercase hex encoding of a digest, the form git and Git LFS name objects by.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Checks downloaded content against the hash it is stored under: the SHA-1
+/// git stores a blob under, computed over `blob <size>\0<content>`, so content
+/// of the wrong length never matches, or the SHA-256 a Git LFS object is named by.
+#[derive(Clone)]
+struct ContentCheck<'a> {
+    expected: &'a str,
+    hasher: Hasher,
+}
+
+#[derive(Clone)]
+enum Hasher {
+    Blob(Sha1),
+    Lfs(sha2::Sha256),
+}
+
+impl<'a> ContentCheck<'a> {
+    fn blob(file: &'a GitHubFile) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("blob {}\0", file.size).as_bytes());
+        Self {
+            expected: &file.sha,
+            hasher: Hasher::Blob(hasher),
+        }
+    }
+
+    fn lfs(object: &'a LfsObject) -> Self {
+        Self {
+            expected: &object.oid,
+            hasher: Hasher::Lfs(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Blob(hasher) => hasher.update(chunk),
+            Hasher::Lfs(hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        let (kind, actual) = match self.hasher {
+            Hasher::Blob(hasher) => ("blob", to_hex(&hasher.finalize())),
+            Hasher::Lfs(hasher) => ("LFS object", to_hex(&hasher.finalize())),
+        };
+        if actual.eq_ignore_ascii_case(self.expected) {
+            Ok(())
+        } else {
+            Err(format!("content does not match {} {} (got {})", kind, self.expected, actual))
+        }
+    }
+}
+
+/// Reads a response body into memory, giving up once no data has arrived for
+/// `IDLE_TIMEOUT`.
+async fn read_body(response: reqwest::Response) -> Result<Vec<u8>, String> {
+    let mut body = response.bytes_stream();
+    let mut content = Vec::new();
+    loop {
+        match tokio::time::timeout(IDLE_TIMEOUT, body.next()).await {
+            Ok(Some(Ok(chunk))) => content.extend_from_slice(&chunk),
+            Ok(Some(Err(e))) => return Err(e.to_string()),
+            Ok(None) => return Ok(content),
+            Err(_) => return Err(format!("no data received for {}s", IDLE_TIMEOUT.as_secs())),
+        }
+    }
+}
+
+/// Downloads the body of `request` into memory. With `check`, the content is
+/// checked, and a mismatch is retried like a failed transfer.
+async fn get_with_retry(
+    client: &Client,
+    request: reqwest::Request,
+    path: &str,
+    check: Option<ContentCheck<'_>>,
+) -> anyhow::Result<Vec<u8>> {
+    let max_retries = 3;
+    let mut retry_count = 0;
+
+    loop {
+        let attempt = request.try_clone().expect("GET requests have no body to clone");
+        let error = match client.execute(attempt).await {
+            Ok(response) => {
+                if !response.status().is_success() {
+                    anyhow::bail!(
+                        "Failed to download file: {}\nStatus: {}\nURL: {}",
+                        path,
+                        response.status(),
+                        request.url()
+                    );
+                }
+                match read_body(response).await {
+                    Ok(content) => {
+                        let Some(mut check) = check.clone() else {
+                            return Ok(content);
+                        };
+                        check.update(&content);
+                        match check.finish() {
+                            Ok(()) => return Ok(content),
+                            Err(e) => e,
+                        }
                     }
-                    
-                    match response.bytes().await {
-                        Ok(content) => {
-                            let file_path = output_path.join(&file.path);
-                            if let Some(parent) = file_path.parent() {
-                                fs::create_dir_all(parent).await?;
+                    Err(e) => e,
+                }
+            }
+            Err(e) => e.to_string(),
+        };
+        retry_count += 1;
+        if retry_count >= max_retries {
+            anyhow::bail!("Failed to download {} after {} retries: {}", path, max_retries, error);
+        }
+        tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
+    }
+}
+
+/// Streams the body of `request` into `out` chunk by chunk, advancing `pb` as bytes
+/// arrive. Whatever `out` already holds is kept, and only the rest is requested
+/// with a `Range` header; a server that ignores it sends the whole file, which
+/// replaces it. A transfer that fails or stalls is retried from where it stopped,
+/// so on error `out` holds what was received. Content that fails `check`, if
+/// given, is discarded and retried from the start.
+async fn stream_with_retry(
+    client: &Client,
+    request: reqwest::Request,
+    path: &str,
+    check: Option<ContentCheck<'_>>,
+    out: &mut File,
+    pb: &ProgressBar,
+) -> anyhow::Result<()> {
+    let max_retries = 3;
+    let mut retry_count = 0;
+    // Bytes of this file currently counted in `pb`.
+    let mut counted = 0;
+
+    loop {
+        let mut offset = out.seek(SeekFrom::End(0)).await?;
+        pb.inc(offset);
+        pb.dec(counted);
+        counted = offset;
+        let mut attempt = request.try_clone().expect("GET requests have no body to clone");
+        if offset > 0 {
+            attempt
+                .headers_mut()
+                .insert(RANGE, HeaderValue::from_str(&format!("bytes={}-", offset))?);
+        }
+        let error = match client.execute(attempt).await {
+            Ok(response) if response.status() == StatusCode::RANGE_NOT_SATISFIABLE => {
+                out.set_len(0).await?;
+                format!("cannot continue from byte {}", offset)
+            }
+            Ok(response) => {
+                if !response.status().is_success() {
+                    pb.dec(counted);
+                    anyhow::bail!(
+                        "Failed to download file: {}\nStatus: {}\nURL: {}",
+                        path,
+                        response.status(),
+                        request.url()
+                    );
+                }
+                if offset > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+                    out.set_len(0).await?;
+                    out.rewind().await?;
+                    pb.dec(offset);
+                    counted = 0;
+                    offset = 0;
+                }
+                let mut check = check.clone();
+                if let (Some(check), true) = (&mut check, offset > 0) {
+                    hash_written(out, check).await?;
+                }
+                let mut body = response.bytes_stream();
+                loop {
+                    match tokio::time::timeout(IDLE_TIMEOUT, body.next()).await {
+                        Ok(Some(Ok(chunk))) => {
+                            out.write_all(&chunk).await?;
+                            if let Some(check) = &mut check {
+                                check.update(&chunk);
                             }
-                            let mut f = File::create(&file_path).await?;
-                            f.write_all(&content).await?;
-                            pb.inc(file.size);
-                            break;
+                            counted += chunk.len() as u64;
+                            pb.inc(chunk.len() as u64);
                         }
-                        Err(e) => {
-                            retry_count += 1;
-                            if retry_count >= max_retries {
-                                anyhow::bail!("Failed to download {} after {} retries: {}", file.path, max_retries, e);
+                        Ok(Some(Err(e))) => break e.to_string(),
+                        Ok(None) => match check.map_or(Ok(()), ContentCheck::finish) {
+                            Ok(()) => {
+                                out.flush().await?;
+                                return Ok(());
                             }
-                            tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
-                        }
-                    }
-                }
-                Err(e) => {
-                    retry_count += 1;
-                    if retry_count >= max_retries {
-                        anyhow::bail!("Failed to download {} after {} retries: {}", file.path, max_retries, e);
+                            Err(e) => {
+                                out.set_len(0).await?;
+                                break e;
+                            }
+                        },
+                        Err(_) => break format!("no data received for {}s", IDLE_TIMEOUT.as_secs()),
                     }
-                    tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
                 }
             }
+            Err(e) => e.to_string(),
+        };
+        out.flush().await?;
+        retry_count += 1;
+        if retry_count >= max_retries {
+            pb.dec(counted);
+            anyhow::bail!("Failed to download {} after {} retries: {}", path, max_retries, error);
         }
+        tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
+    }
+}
+
+/// Feeds what `out` already holds to `check`, leaving `out` positioned at its end.
+async fn hash_written(out: &mut File, check: &mut ContentCheck<'_>) -> std::io::Result<()> {
+    out.rewind().await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match out.read(&mut buffer).await? {
+            0 => return Ok(()),
+            read => check.update(&buffer[..read]),
+        }
+    }
+}
+
+/// Fetches a file's content from its raw `download_url`, falling back to the
+/// blobs API for files that have none (such as files over 1 MB listed by the
+/// contents API) or whose raw URL cannot be served or serves the wrong content.
+async fn fetch_git_content(client: &Client, file: &GitHubFile, options: &DownloadOptions<'_>) -> anyhow::Result<Vec<u8>> {
+    let check = options.verify.then(|| ContentCheck::blob(file));
+    if let Some(download_url) = &file.download_url {
+        let request = authorized_get(client, download_url, &options.token).build()?;
+        let result = get_with_retry(client, request, &file.path, check.clone()).await;
+        if result.is_ok() || file.git_url.is_none() {
+            return result;
+        }
+    }
+    match &file.git_url {
+        Some(git_url) => {
+            let request = authorized_get(client, git_url, &options.token)
+                .header(ACCEPT, RAW_MEDIA_TYPE)
+                .build()?;
+            get_with_retry(client, request, &file.path, check).await
+        }
+        None => anyhow::bail!("No download URL for {} ({})", file.path, file.file_type),
+    }
+}
+
+/// Streams an LFS object into `out`, checked against its SHA-256 unless
+/// verification is off. LFS object URLs carry their own authorization, so no
+/// token is sent.
+async fn stream_lfs_object(
+    client: &Client,
+    object: &LfsObject,
+    path: &str,
+    verify: bool,
+    out: &mut File,
+    pb: &ProgressBar,
+) -> anyhow::Result<()> {
+    let mut request = client.get(&object.href);
+    for (name, value) in &object.headers {
+        request = request.header(name, value);
+    }
+    let check = verify.then(|| ContentCheck::lfs(object));
+    stream_with_retry(client, request.build()?, path, check, out, pb).await
+}
+
+/// The LFS object `content` points at, if it is a pointer `.gitattributes` did
+/// not announce. An object the server cannot serve leaves the pointer file,
+/// with a warning printed clear of `pb`.
+async fn find_lfs_object(
+    lfs: &LfsClient,
+    file: &GitHubFile,
+    content: &[u8],
+    pb: &ProgressBar,
+) -> anyhow::Result<Option<LfsObject>> {
+    let Some(pointer) = lfs::parse_pointer(content) else {
+        return Ok(None);
+    };
+    match lfs.resolve(&[pointer]).await?.remove(0) {
+        Ok(object) => Ok(Some(object)),
+        Err(e) => {
+            pb.suspend(|| {
+                eprintln!(
+                    "{} {}: LFS object unavailable ({}), keeping the pointer file",
+                    console::style("Warning:").yellow().bold(),
+                    file.path,
+                    e
+                )
+            });
+            Ok(None)
+        }
+    }
+}
+
+/// Writes a file's content to `out` as it is downloaded. Only files small enough
+/// to be Git LFS pointers are read whole first, to check whether they are one.
+async fn write_content(
+    client: &Client,
+    file: &GitHubFile,
+    options: &DownloadOptions<'_>,
+    out: &mut File,
+    pb: &ProgressBar,
+) -> anyhow::Result<()> {
+    if let Some(object) = &file.lfs {
+        return stream_lfs_object(client, object, &file.path, options.verify, out, pb).await;
+    }
+    if let (Some(lfs), true) = (&options.lfs, file.size <= lfs::MAX_POINTER_SIZE) {
+        let content = fetch_git_content(client, file, options).await?;
+        out.set_len(0).await?;
+        out.rewind().await?;
+        if let Some(object) = find_lfs_object(lfs, file, &content, pb).await? {
+            // The total counted the pointer, not the object.
+            pb.inc_length(object.size);
+            pb.dec_length(file.size);
+            return stream_lfs_object(client, &object, &file.path, options.verify, out, pb).await;
+        }
+        out.write_all(&content).await?;
+        out.flush().await?;
+        pb.inc(content.len() as u64);
+        return Ok(());
+    }
+
+    // Same fallback as `fetch_git_content`, from the raw URL to the blobs API.
+    let check = options.verify.then(|| ContentCheck::blob(file));
+    if let Some(download_url) = &file.download_url {
+        let request = authorized_get(client, download_url, &options.token).build()?;
+        let result = stream_with_retry(client, request, &file.path, check.clone(), out, pb).await;
+        if result.is_ok() || file.git_url.is_none() {
+            return result;
+        }
+    }
+    match &file.git_url {
+        Some(git_url) => {
+            let request = authorized_get(client, git_url, &options.token)
+                .header(ACCEPT, RAW_MEDIA_TYPE)
+                .build()?;
+            stream_with_retry(client, request, &file.path, check, out, pb).await
+        }
+        None => anyhow::bail!("No download URL for {} ({})", file.path, file.file_type),
+    }
+}
+
+/// Downloads `file` into `output_path`. With a journal, a partial file is kept
+/// for the next run if the download stops, one left by an earlier run of the
+/// same session is continued, and the completed file is recorded.
+async fn download_file_with_retry(
+    client: &Client,
+    file: &GitHubFile,
+    output_path: &Path,
+    options: &DownloadOptions<'_>,
+    pb: &ProgressBar,
+) -> anyhow::Result<()> {
+    let file_path = output_path.join(&file.path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let journal = options.journal;
+    if file.is_symlink() {
+        let content = fetch_git_content(client, file, options).await?;
+        create_symlink(&String::from_utf8_lossy(&content), &file_path).await?;
+        pb.inc(content.len() as u64);
+    } else {
+        let partial = match journal {
+            Some(journal) => {
+                let partial = PartialPath::resumable(file_path);
+                journal.record_partial(partial.temp_path())?;
+                partial
+            }
+            None => PartialPath::new(file_path),
+        };
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(!journal.is_some_and(Journal::is_resumed))
+            .open(partial.temp_path())
+            .await?;
+        write_content(client, file, options, &mut f, pb).await?;
+        drop(f);
+        partial.persist().await?;
+    }
+    // A file missing from the journal is only hashed again next time.
+    if let Some(journal) = journal {
+        let _ = journal.record(file);
     }
     Ok(())
 }
 
+/// A file written under a temporary name next to its final path, and renamed
+/// into place only once complete, so that a failed or interrupted download
+/// never leaves a truncated file behind. Dropping it before `persist` removes
+/// the temporary file, unless it is resumable (kept track of by a journal) and
+/// holds data to continue from.
+struct PartialPath {
+    path: PathBuf,
+    temp_path: PathBuf,
+    persisted: bool,
+    resumable: bool,
+}
+
+impl PartialPath {
+    fn new(path: PathBuf) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = path.with_file_name(format!(".{}.part", name));
+        Self {
+            path,
+            temp_path,
+            persisted: false,
+            resumable: false,
+        }
+    }
+
+    fn resumable(path: PathBuf) -> Self {
+        let mut partial = Self::new(path);
+        partial.resumable = true;
+        partial
+    }
+
+    fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    async fn persist(mut self) -> anyhow::Result<()> {
+        fs::rename(&self.temp_path, &self.path).await?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for PartialPath {
+    fn drop(&mut self) {
+        let keep = self.resumable && std::fs::metadata(&self.temp_path).is_ok_and(|m| m.len() > 0);
+        if !self.persisted && !keep {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// A symlink blob holds the link target. Platforms without symlinks get a plain
+/// file containing the target, as git does with `core.symlinks=false`.
+async fn create_symlink(target: &str, link_path: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(link_path).await.is_ok() {
+        fs::remove_file(link_path).await?;
+    }
+    #[cfg(unix)]
+    fs::symlink(target, link_path).await?;
+    #[cfg(not(unix))]
+    fs::write(link_path, target).await?;
+    Ok(())
+}
+
+/// Whether `path` already holds `file`: a symlink to the same target, or a file
+/// that hashes to the listed blob. LFS files hold the object rather than the
+/// pointer blob, so they are compared against the object's SHA-256 instead.
+fn is_up_to_date(file: &GitHubFile, path: &Path) -> bool {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return false;
+    };
+    if metadata.file_type().is_symlink() {
+        let Ok(target) = std::fs::read_link(path) else {
+            return false;
+        };
+        let target = target.to_string_lossy();
+        let mut check = ContentCheck::blob(file);
+        check.update(target.as_bytes());
+        return file.is_symlink() && check.finish().is_ok();
+    }
+    if !metadata.is_file() || metadata.len() != file.size {
+        return false;
+    }
+    let Ok(mut local) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut check = match &file.lfs {
+        Some(object) => ContentCheck::lfs(object),
+        None => ContentCheck::blob(file),
+    };
+    let mut buffer = vec![0; 64 * 1024];
+    while let Ok(read @ 1..) = std::io::Read::read(&mut local, &mut buffer) {
+        check.update(&buffer[..read]);
+    }
+    check.finish().is_ok()
+}
+
+/// Whether `path` is still what an earlier run wrote for `file`, going by its
+/// type and size alone.
+fn is_still_written(file: &GitHubFile, path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| {
+        let is_symlink = metadata.file_type().is_symlink();
+        is_symlink == file.is_symlink() && (is_symlink || metadata.len() == file.size)
+    })
+}
+
+/// Splits `files` into those still to download and those `output_path` already
+/// holds unchanged, hashing up to `concurrency` local files at a time. Files
+/// `journal` lists as completed are not hashed again.
+pub async fn split_up_to_date(
+    files: Vec<GitHubFile>,
+    output_path: &str,
+    concurrency: usize,
+    journal: Option<&Journal>,
+) -> (Vec<GitHubFile>, Vec<GitHubFile>) {
+    let output_path = Path::new(output_path).to_path_buf();
+    let checked: Vec<(GitHubFile, bool)> = stream::iter(files)
+        .map(|file| {
+            let path = output_path.join(&file.path);
+            let completed = journal.is_some_and(|journal| journal.is_completed(&file));
+            tokio::task::spawn_blocking(move || {
+                let current = if completed {
+                    is_still_written(&file, &path)
+                } else {
+                    is_up_to_date(&file, &path)
+                };
+                (file, current)
+            })
+        })
+        .buffered(concurrency.max(1))
+        .map(|result| result.expect("hashing a local file panicked"))
+        .collect()
+        .await;
+    let (current, missing): (Vec<_>, Vec<_>) = checked.into_iter().partition(|(_, current)| *current);
+    (
+        missing.into_iter().map(|(file, _)| file).collect(),
+        current.into_iter().map(|(file, _)| file).collect(),
+    )
+}
+
+/// A downloaded archive entry waiting to be added to the ZIP file.
+enum ZipEntry {
+    Symlink(Vec<u8>),
+    File(std::fs::File),
+}
+
+/// What became of the files of a download.
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    pub completed: Vec<String>,
+    /// `(path, error)` of the files that could not be downloaded.
+    pub failed: Vec<(String, String)>,
+    /// Files not downloaded because the download was interrupted with Ctrl-C.
+    pub interrupted: Vec<String>,
+}
+
+/// How to download files.
+#[derive(Clone)]
+pub struct DownloadOptions<'a> {
+    /// Maximum number of downloads in flight at once.
+    pub concurrency: usize,
+    /// Sent with requests for raw files and blobs, but not for LFS objects.
+    pub token: Option<String>,
+    /// Follows Git LFS pointers to their objects, unless `None`.
+    pub lfs: Option<LfsClient>,
+    /// Checks downloaded files against their blob SHA, or Git LFS objects against their SHA-256.
+    pub verify: bool,
+    /// Records the files written into a directory, so that an unfinished
+    /// download can be resumed.
+    pub journal: Option<&'a Journal>,
+}
+
+/// Downloads `files` into `output_path`, or into a ZIP archive there. Ctrl-C
+/// stops the download: transfers in flight are dropped, leaving their partial
+/// files for the next run to resume if there is a journal, and the report
+/// tells which files were not downloaded.
 pub async fn download_files(
     files: Vec<GitHubFile>,
     output_path: &str,
-    concurrency: u8,
     as_zip: bool,
-) -> anyhow::Result<()> {
+    options: &DownloadOptions<'_>,
+) -> anyhow::Result<DownloadReport> {
     
-    let client = Arc::new(
-        Client::builder()
-            .pool_max_idle_per_host(20)
-            .pool_idle_timeout(Duration::from_secs(30))
-            .timeout(Duration::from_secs(30))
-            .connection_verbose(false)
-            .tcp_keepalive(Duration::from_secs(10))
-            .http1_only() // Force HTTP/1.1 to avoid HTTP/2 frame issues
-            .user_agent("Mozilla/5.0 (compatible; downloader/1.0)")
-            .build()?
-    );
+    let client = Client::builder()
+        .pool_max_idle_per_host(20)
+        .pool_idle_timeout(Duration::from_secs(30))
+        // Large files may take longer than any fixed limit; stalled
+        // transfers are caught by `IDLE_TIMEOUT` instead.
+        .connect_timeout(Duration::from_secs(30))
+        .connection_verbose(false)
+        .tcp_keepalive(Duration::from_secs(10))
+        .http1_only() // Force HTTP/1.1 to avoid HTTP/2 frame issues
+        .user_agent("Mozilla/5.0 (compatible; downloader/1.0)")
+        .build()?;
     
     let output_path = Path::new(output_path).to_path_buf();
     
     if !output_path.exists() {
         fs::create_dir_all(&output_path).await?;
     }
+    if let Some(journal) = options.journal {
+        journal.start()?;
+    }
     
     let total_size = files.iter().map(|f| f.size).sum();
     let pb = ProgressBar::new(total_size);
@@ -99,81 +632,257 @@ pub async fn download_files(
         .unwrap()
         .progress_chars("██▓▒░"),
     );
+
+    let paths: Vec<String> = files.iter().map(|f| f.path.clone()).collect();
+    let concurrency = options.concurrency.clamp(1, MAX_CONCURRENT_DOWNLOADS);
+    let completed = Mutex::new(Vec::new());
+    let failed = Mutex::new(Vec::new());
     
-    if as_zip {
-        let zip_file_path = if output_path.is_dir() {
-            output_path.join("archive.zip")
+    let download = async {
+        if as_zip {
+            let zip_file_path = if output_path.is_dir() {
+                output_path.join("archive.zip")
+            } else {
+                output_path.clone()
+            };
+            let archive = PartialPath::new(zip_file_path);
+            let zip_file = std::fs::File::create(archive.temp_path())?;
+            let mut zip = ZipWriter::new(zip_file);
+            let entry_options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            
+            // Entries are streamed into anonymous temporary files concurrently, then
+            // copied into the archive one at a time, in order.
+            let mut downloads = stream::iter(files)
+                .map(|file| {
+                    let (client, pb) = (&client, &pb);
+                    async move {
+                        if file.is_symlink() {
+                            let content = fetch_git_content(client, &file, options).await?;
+                            pb.inc(content.len() as u64);
+                            return Ok::<_, anyhow::Error>((file, ZipEntry::Symlink(content)));
+                        }
+                        let mut spool = File::from_std(tempfile::tempfile()?);
+                        write_content(client, &file, options, &mut spool, pb).await?;
+                        spool.rewind().await?;
+                        Ok((file, ZipEntry::File(spool.into_std().await)))
+                    }
+                })
+                .buffered(concurrency);
+
+            let mut added = Vec::new();
+            while let Some(download) = downloads.next().await {
+                let (file, entry) = download?;
+                match entry {
+                    ZipEntry::Symlink(target) => {
+                        zip.add_symlink(file.path.clone(), String::from_utf8_lossy(&target), entry_options)?;
+                    }
+                    ZipEntry::File(mut spool) => {
+                        zip.start_file(file.path.clone(), entry_options)?;
+                        std::io::copy(&mut spool, &mut zip)?;
+                    }
+                }
+                added.push(file.path);
+            }
+            zip.finish()?;
+            archive.persist().await?;
+            // Entries only count as downloaded once the archive is complete.
+            *completed.lock().unwrap() = added;
         } else {
-            output_path
-        };
-        let zip_file = std::fs::File::create(&zip_file_path)?;
-        let mut zip = ZipWriter::new(zip_file);
-        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
-        
-        let mut downloads = Vec::new();
-        for file in files {
-            if let Some(download_url) = file.download_url {
-                let client_clone = Arc::clone(&client);
-                let pb_clone = pb.clone();
-                let download = tokio::spawn(async move {
-                    let max_retries = 3;
-                    let mut retry_count = 0;
-                    
-                    loop {
-                        match client_clone.get(&download_url).send().await {
-                            Ok(response) => {
-                                match response.bytes().await {
-                                    Ok(content) => {
-                                        pb_clone.inc(file.size);
-                                        return Ok::<_, anyhow::Error>((file.path, content));
-                                    }
-                                    Err(e) => {
-                                        retry_count += 1;
-                                        if retry_count >= max_retries {
-                                            return Err(anyhow::anyhow!("Failed after {} retries: {}", max_retries, e));
-                                        }
-                                        tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                retry_count += 1;
-                                if retry_count >= max_retries {
-                                    return Err(anyhow::anyhow!("Failed after {} retries: {}", max_retries, e));
-                                }
-                                tokio::time::sleep(Duration::from_millis(100 * retry_count)).await;
+
+            // Links are created once every regular file is in place, so that nothing
+            // is ever written through a link that points elsewhere.
+            let (symlinks, files): (Vec<_>, Vec<_>) = files.into_iter().partition(GitHubFile::is_symlink);
+            
+            for batch in [files, symlinks] {
+                stream::iter(batch)
+                    .for_each_concurrent(concurrency, |file| {
+                        let (client, output_path, pb) = (&client, &output_path, &pb);
+                        let (completed, failed) = (&completed, &failed);
+                        async move {
+                            match download_file_with_retry(client, &file, output_path, options, pb).await {
+                                Ok(()) => completed.lock().unwrap().push(file.path),
+                                Err(e) => failed.lock().unwrap().push((file.path, e.to_string())),
                             }
                         }
-                    }
-                });
-                downloads.push(download);
+                    })
+                    .await;
             }
         }
-        
-        for download in downloads {
-            let (path, content) = download.await??;
-            zip.start_file(path, options)?;
-            zip.write_all(&content)?;
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let interrupted = tokio::select! {
+        result = download => {
+            result?;
+            false
         }
-        zip.finish()?;
+        _ = tokio::signal::ctrl_c() => true,
+    };
+    if interrupted {
+        pb.abandon();
     } else {
-        
-        let effective_concurrency = std::cmp::min(concurrency as usize, 10);
-        
-        stream::iter(files)
-            .for_each_concurrent(effective_concurrency, |file| {
-                let client = Arc::clone(&client);
-                let output_path = output_path.clone();
-                let pb = pb.clone();
-                async move {
-                    if let Err(e) = download_file_with_retry(client, file, output_path, pb).await {
-                        eprintln!("{} {}", console::style("Error downloading file:").red().bold(), e);
-                    }
+        pb.finish_with_message("⚡ Download complete! ⚡!");
+    }
+
+    let completed = completed.into_inner().unwrap_or_default();
+    let failed = failed.into_inner().unwrap_or_default();
+    let finished: HashSet<&String> = completed.iter().chain(failed.iter().map(|(path, _)| path)).collect();
+    let interrupted = paths.iter().filter(|path| !finished.contains(path)).cloned().collect();
+    Ok(DownloadReport {
+        completed,
+        failed,
+        interrupted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_content_check() {
+        // `echo hello | git hash-object --stdin`
+        let readme = GitHubFile::fixture("README.md", "ce013625030ba8dba906f756967f9e9ca394464a", 6);
+        let mut check = ContentCheck::blob(&readme);
+        check.update(b"hel");
+        check.update(b"lo\n");
+        assert_eq!(check.finish(), Ok(()));
+
+        let mut stale = ContentCheck::blob(&readme);
+        stale.update(b"hello, world\n");
+        assert!(stale.finish().unwrap_err().starts_with("content does not match blob ce01362"));
+
+        // `printf 'HELLO\n' | sha256sum`
+        let object = LfsObject {
+            oid: "3b09aeb6f5f5336beb205d7f720371bc927cd46c21922e334d47ba264acb5ba4".to_string(),
+            size: 6,
+            href: String::new(),
+            headers: Vec::new(),
+        };
+        let mut check = ContentCheck::lfs(&object);
+        check.update(b"HELLO\n");
+        assert_eq!(check.finish(), Ok(()));
+        let mut stale = ContentCheck::lfs(&object);
+        stale.update(b"hello\n");
+        assert!(stale.finish().unwrap_err().starts_with("content does not match LFS object 3b09aeb"));
+    }
+
+    #[test]
+    fn test_partial_path_is_kept_only_when_resumable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let temp_path = dir.path().join(".file.txt.part");
+
+        let partial = PartialPath::new(path.clone());
+        std::fs::write(partial.temp_path(), "half").unwrap();
+        drop(partial);
+        assert!(!temp_path.exists());
+
+        let partial = PartialPath::resumable(path.clone());
+        std::fs::write(partial.temp_path(), "").unwrap();
+        drop(partial);
+        assert!(!temp_path.exists());
+
+        let partial = PartialPath::resumable(path.clone());
+        std::fs::write(partial.temp_path(), "half").unwrap();
+        drop(partial);
+        assert_eq!(std::fs::read_to_string(&temp_path).unwrap(), "half");
+
+        let partial = PartialPath::new(path.clone());
+        std::fs::write(partial.temp_path(), "whole").unwrap();
+        tokio::runtime::Runtime::new().unwrap().block_on(partial.persist()).unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "whole");
+    }
+
+    /// Answers one connection per scripted `(status, body)` response, in order,
+    /// and returns its URL along with the `Range` header of each request.
+    async fn respond(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    socket.read_exact(&mut byte).await.unwrap();
+                    request.push(byte[0]);
                 }
-            })
-            .await;
+                let range = String::from_utf8_lossy(&request)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: ").map(str::to_string));
+                seen.lock().unwrap().push(range);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, ranges)
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_retry_resumes_and_restarts() {
+        // `echo hello | git hash-object --stdin`
+        let readme = GitHubFile::fixture("README.md", "ce013625030ba8dba906f756967f9e9ca394464a", 6);
+        let client = Client::new();
+        let range = |from: &str| Some(format!("bytes={}-", from));
+        let cases = [
+            // Continued where the partial file stops.
+            ("hel", vec![(206, "lo\n")], vec![range("3")]),
+            // A server that ignores `Range` sends the whole file, which replaces it.
+            ("hel", vec![(200, "hello\n")], vec![range("3")]),
+            // A partial file the server cannot continue is started over.
+            ("hello\n!", vec![(416, ""), (200, "hello\n")], vec![range("7"), None]),
+            // Content that does not match the blob is discarded and retried.
+            ("", vec![(200, "HELLO\n"), (200, "hello\n")], vec![None, None]),
+            // So is a continued partial file that turns out not to match.
+            ("HEL", vec![(206, "lo\n"), (200, "hello\n")], vec![range("3"), None]),
+        ];
+        for (written, responses, expected_ranges) in cases {
+            let (url, ranges) = respond(responses).await;
+            let mut out = File::from_std(tempfile::tempfile().unwrap());
+            out.write_all(written.as_bytes()).await.unwrap();
+            let pb = ProgressBar::hidden();
+            let request = client.get(&url).build().unwrap();
+            stream_with_retry(&client, request, "README.md", Some(ContentCheck::blob(&readme)), &mut out, &pb)
+                .await
+                .unwrap();
+
+            let mut content = String::new();
+            out.rewind().await.unwrap();
+            out.read_to_string(&mut content).await.unwrap();
+            assert_eq!(content, "hello\n", "starting from {:?}", written);
+            assert_eq!(pb.position(), 6, "starting from {:?}", written);
+            assert_eq!(*ranges.lock().unwrap(), expected_ranges, "starting from {:?}", written);
+        }
+    }
+
+    #[test]
+    fn test_is_up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut readme = GitHubFile::fixture("README.md", "ce013625030ba8dba906f756967f9e9ca394464a", 6);
+        let path = dir.path().join("README.md");
+        assert!(!is_up_to_date(&readme, &path));
+        std::fs::write(&path, "hello\n").unwrap();
+        assert!(is_up_to_date(&readme, &path));
+        std::fs::write(&path, "HELLO\n").unwrap();
+        assert!(!is_up_to_date(&readme, &path));
+
+        // `printf 'HELLO\n' | sha256sum`
+        readme.lfs = Some(LfsObject {
+            oid: "3b09aeb6f5f5336beb205d7f720371bc927cd46c21922e334d47ba264acb5ba4".to_string(),
+            size: 6,
+            href: String::new(),
+            headers: Vec::new(),
+        });
+        assert!(is_up_to_date(&readme, &path));
     }
-    
-    pb.finish_with_message("⚡ Download complete! ⚡!");
-    Ok(())
 }
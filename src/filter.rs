@@ -0,0 +1,238 @@
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Decides which files of a listing to download, from `--include` and
+/// `--exclude` globs and the rules of a `--filter-file`.
+///
+/// Paths are matched relative to the downloaded directory they are in, with gitignore
+/// pattern syntax: a pattern without a slash matches a name at any depth
+/// (`*.proto`), one with a slash is anchored (`tests/**`, `/docs`), a trailing
+/// slash only matches directories, and a pattern matching a directory covers
+/// everything in it.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    /// The downloaded directories.
+    bases: Vec<String>,
+    includes: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+    /// Filter file rules in file order; the last one matching a path decides.
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    matcher: GlobMatcher,
+    dir_only: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern`: deselects what earlier rules selected.
+    negated: bool,
+}
+
+impl PathFilter {
+    pub fn new(bases: &[String], includes: &[String], excludes: &[String]) -> anyhow::Result<Self> {
+        let compile_all = |patterns: &[String]| patterns.iter().map(|p| compile(p)).collect::<anyhow::Result<Vec<_>>>();
+        Ok(Self {
+            bases: bases.iter().map(|base| base.trim_matches('/').to_string()).collect(),
+            includes: compile_all(includes)?,
+            excludes: compile_all(excludes)?,
+            rules: Vec::new(),
+        })
+    }
+
+    /// Adds the rules of a filter file. Like a sparse-checkout file, each line
+    /// selects the paths it matches and a `!` line deselects them again; once a
+    /// filter file is given, paths no rule selects are not downloaded.
+    pub fn add_rules(&mut self, content: &str) -> anyhow::Result<()> {
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let pattern = compile(pattern).map_err(|e| anyhow::anyhow!("line {}: {}", number + 1, e))?;
+            self.rules.push(Rule { pattern, negated });
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty() && self.rules.is_empty()
+    }
+
+    /// Whether the file at `path` (relative to the repository) is downloaded.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = self.relative(path);
+        (self.includes.is_empty() || self.includes.iter().any(|p| p.matches(path, false)))
+            && !self.excludes.iter().any(|p| p.matches(path, false))
+            && (self.rules.is_empty() || self.last_rule(path, false).is_some_and(|rule| !rule.negated))
+    }
+
+    /// Whether nothing inside the directory at `path` can be downloaded, so it
+    /// does not need to be listed.
+    pub fn excludes_dir(&self, path: &str) -> bool {
+        let path = self.relative(path);
+        if self.excludes.iter().any(|p| p.matches(path, true)) {
+            return true;
+        }
+        if self.rules.is_empty() {
+            return false;
+        }
+        // Everything inside matches the same rules the directory does, so the
+        // directory's last rule decides for its contents unless a later rule
+        // could select something inside it.
+        let position = self
+            .rules
+            .iter()
+            .rposition(|rule| rule.pattern.matches(path, true));
+        let later_selects = |from: usize| self.rules[from..].iter().any(|rule| !rule.negated);
+        match position {
+            Some(i) => self.rules[i].negated && !later_selects(i + 1),
+            None => !later_selects(0),
+        }
+    }
+
+    fn last_rule(&self, path: &str, is_dir: bool) -> Option<&Rule> {
+        self.rules.iter().rev().find(|rule| rule.pattern.matches(path, is_dir))
+    }
+
+    /// `path` relative to the deepest downloaded directory it is in.
+    fn relative<'a>(&self, path: &'a str) -> &'a str {
+        let mut relative = path;
+        for base in self.bases.iter().filter(|base| !base.is_empty()) {
+            let candidate = match path.strip_prefix(base.as_str()) {
+                Some("") => path.rsplit('/').next().unwrap_or(path),
+                Some(rest) if rest.starts_with('/') => &rest[1..],
+                _ => continue,
+            };
+            if candidate.len() < relative.len() {
+                relative = candidate;
+            }
+        }
+        relative
+    }
+}
+
+impl Pattern {
+    /// Whether `path` or one of the directories it is in matches.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if (is_dir || !self.dir_only) && self.matcher.is_match(path) {
+            return true;
+        }
+        let mut candidate = path;
+        while let Some((parent, _)) = candidate.rsplit_once('/') {
+            if self.matcher.is_match(parent) {
+                return true;
+            }
+            candidate = parent;
+        }
+        false
+    }
+}
+
+fn compile(pattern: &str) -> anyhow::Result<Pattern> {
+    let dir_only = pattern.ends_with('/');
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    // `dir/**` is the same as `dir/`, since matching a directory covers its contents.
+    let trimmed = pattern.trim_matches('/');
+    let (trimmed, dir_only) = match trimmed.strip_suffix("/**") {
+        Some(dir) => (dir, true),
+        None => (trimmed, dir_only),
+    };
+    let glob = if anchored {
+        trimmed.to_string()
+    } else {
+        format!("**/{}", trimmed)
+    };
+    let matcher = GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid pattern '{}': {}", pattern, e))?
+        .compile_matcher();
+    Ok(Pattern { matcher, dir_only })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(includes: &[&str], excludes: &[&str]) -> PathFilter {
+        let owned = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PathFilter::new(&owned(&["api"]), &owned(includes), &owned(excludes)).unwrap()
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let protos = filter(&["*.proto"], &[]);
+        assert!(protos.matches("api/v1/service.proto"));
+        assert!(protos.matches("api/root.proto"));
+        assert!(!protos.matches("api/v1/service.rs"));
+
+        let no_tests = filter(&[], &["tests/**"]);
+        assert!(no_tests.matches("api/src/lib.rs"));
+        assert!(!no_tests.matches("api/tests/it.rs"));
+        assert!(no_tests.matches("api/src/tests/unit.rs"));
+        assert!(no_tests.excludes_dir("api/tests"));
+        assert!(!no_tests.excludes_dir("api/src"));
+
+        let both = filter(&["v1", "*.md"], &["*_test.proto"]);
+        assert!(both.matches("api/v1/a.proto"));
+        assert!(!both.matches("api/v1/a_test.proto"));
+        assert!(both.matches("api/v2/README.md"));
+        assert!(!both.matches("api/v2/b.proto"));
+
+        assert!(filter(&[], &[]).is_empty());
+        assert!(PathFilter::new(&[], &["a[".to_string()], &[]).is_err());
+
+        // With several downloaded directories, paths are relative to the one they are in.
+        let bases = ["api".to_string(), "schemas/v1".to_string()];
+        let top_level = PathFilter::new(&bases, &["/*.proto".to_string()], &[]).unwrap();
+        assert!(top_level.matches("api/a.proto"));
+        assert!(top_level.matches("schemas/v1/b.proto"));
+        assert!(!top_level.matches("schemas/v1/nested/c.proto"));
+    }
+
+    #[test]
+    fn test_filter_file_rules() {
+        let mut vendored = filter(&[], &[]);
+        vendored
+            .add_rules("# everything at the top, minus tests\n/*\n!/tests/\n!*.bak\nbuild/\n!**/tmp/\n\\!important\n")
+            .unwrap();
+        assert!(vendored.matches("api/lib.rs"));
+        assert!(vendored.matches("api/src/deep/lib.rs"));
+        assert!(!vendored.matches("api/tests/it.rs"));
+        assert!(!vendored.matches("api/src/old.bak"));
+        assert!(vendored.matches("api/src/build/out.o"));
+        assert!(!vendored.matches("api/src/build/tmp/x.o"));
+        assert!(vendored.matches("api/!important"));
+
+        // Directory-only patterns do not match files of the same name.
+        let mut dirs = filter(&[], &[]);
+        dirs.add_rules("docs/\n").unwrap();
+        assert!(dirs.matches("api/docs/guide.md"));
+        assert!(!dirs.matches("api/src/docs"));
+
+        // Unanchored rules may select something anywhere, so no directory is skipped.
+        assert!(!dirs.excludes_dir("api/src"));
+        assert!(!dirs.excludes_dir("api/docs"));
+        let mut top_level = filter(&[], &[]);
+        top_level.add_rules("/*\n!/tests/\n").unwrap();
+        assert!(top_level.excludes_dir("api/tests"));
+        assert!(!top_level.excludes_dir("api/src"));
+        // `build/` could select `tests/build/`.
+        assert!(!vendored.excludes_dir("api/tests"));
+        // A later rule may still select something inside an excluded directory.
+        let mut reinclude = filter(&[], &[]);
+        reinclude.add_rules("/*\n!/gen/\n*.proto\n").unwrap();
+        assert!(!reinclude.excludes_dir("api/gen"));
+        assert!(reinclude.matches("api/gen/a.proto"));
+        assert!(!reinclude.matches("api/gen/a.rs"));
+
+        assert!(filter(&[], &[]).add_rules("ok\n[\n").unwrap_err().to_string().starts_with("line 2:"));
+    }
+}
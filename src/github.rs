@@ -1,15 +1,112 @@
-use futures_util::future::try_join_all;
-use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use indicatif::ProgressBar;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RETRY_AFTER, USER_AGENT,
+};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use thiserror::Error;
-use url::Url;
+
+use crate::cache::{CachedResponse, ResponseCache};
+use crate::filter::PathFilter;
+use crate::{lfs, repo_url};
 
 #[derive(Debug, Clone)]
 pub struct RepositoryInfo {
+    /// Web host of the repository, `github.com` or a GitHub Enterprise Server host.
+    pub host: String,
+    /// REST API root, e.g. `https://api.github.com` or `https://ghe.example.com/api/v3`.
+    pub api_base: String,
     pub user: String,
     pub repository: String,
     pub git_reference: Option<String>,
     pub directory: String,
+    /// Set when the ref came from a `/tree/<ref>/<path>` or `/blob/<ref>/<path>` URL, where the split
+    /// between ref and path is ambiguous until checked against the repo's refs.
+    pub reference_in_path: bool,
+    /// Number of the pull request whose head should be downloaded.
+    pub pull_request: Option<u64>,
+    pub resolved_ref: Option<ResolvedRef>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    Branch,
+    Tag,
+    Commit,
+    PullRequest,
+}
+
+impl std::fmt::Display for RefKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefKind::Branch => write!(f, "branch"),
+            RefKind::Tag => write!(f, "tag"),
+            RefKind::Commit => write!(f, "commit"),
+            RefKind::PullRequest => write!(f, "pull request"),
+        }
+    }
+}
+
+/// A git reference pinned to the commit it pointed at when the clone started.
+#[derive(Debug, Clone)]
+pub struct ResolvedRef {
+    pub name: String,
+    pub kind: RefKind,
+    pub sha: String,
+    /// Full name of the fork a pull request's head lives in, if not the base repository.
+    pub fork: Option<String>,
+}
+
+impl ResolvedRef {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+}
+
+impl RepositoryInfo {
+    fn repo_api_url(&self) -> String {
+        format!("{}/repos/{}/{}", self.api_base, self.user, self.repository)
+    }
+
+    /// Web URL of the repository, e.g. `https://github.com/owner/repo`.
+    pub fn web_url(&self) -> String {
+        let base = if self.api_base == "https://api.github.com" {
+            "https://github.com"
+        } else {
+            self.api_base.trim_end_matches("/api/v3")
+        };
+        format!("{}/{}/{}", base, self.user, self.repository)
+    }
+
+    /// Raw file URL for `path` at `commit`, as the contents API would report it.
+    fn raw_url(&self, commit: &str, path: &str) -> anyhow::Result<String> {
+        let base = if self.api_base == "https://api.github.com" {
+            "https://raw.githubusercontent.com".to_string()
+        } else {
+            format!("{}/raw", self.api_base.trim_end_matches("/api/v3"))
+        };
+        let mut url = url::Url::parse(&base)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid raw URL base: {}", base))?
+            .pop_if_empty()
+            .extend([self.user.as_str(), self.repository.as_str(), commit])
+            .extend(path.split('/'));
+        Ok(url.to_string())
+    }
+
+    /// The ref to send to the API: the pinned commit once resolved, otherwise
+    /// whatever the URL named (or `None` for the default branch).
+    fn api_ref(&self) -> Option<&str> {
+        self.resolved_ref
+            .as_ref()
+            .map(|r| r.sha.as_str())
+            .or(self.git_reference.as_deref())
+    }
 }
 
 #[derive(Error, Debug)]
@@ -18,6 +115,22 @@ pub enum RepoInfoError {
     InvalidUrl(String),
     #[error("URL does not point to a repository: {0}")]
     NotARepository(String),
+    #[error("Unsupported host '{0}': expected github.com, or pass --api-url/--host-token for a GitHub Enterprise host")]
+    UnsupportedHost(String),
+    #[error("Unsupported URL scheme '{0}'")]
+    UnsupportedScheme(String),
+    #[error("URL does not point to a directory or file: /{0}")]
+    UnsupportedPath(String),
+    #[error("URL is missing a branch, tag or commit: {0}")]
+    MissingReference(String),
+    #[error("Invalid pull request number '{0}'")]
+    InvalidPullRequest(String),
+    #[error("Raw URL must point to a file: {0}")]
+    MissingPath(String),
+    #[error("Expected shorthand of the form owner/repo[/path][@ref], got '{0}'")]
+    InvalidShorthand(String),
+    #[error("the submodule is on {host}, not {superproject}; submodules on another host are not supported, download it separately")]
+    OtherHost { host: String, superproject: String },
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -25,82 +138,353 @@ pub struct GitHubFile {
     pub path: String,
     pub name: String,
     #[serde(rename = "type")]
-    pub file_type: String, // "file" or "dir"
+    pub file_type: String, // "file", "dir", "symlink" or "submodule"
     pub download_url: Option<String>,
+    /// Git object API URL: the blob for files and symlinks, which can be fetched
+    /// when there is no `download_url`.
+    pub git_url: Option<String>,
     pub sha: String,
     pub size: u64,
+    /// Where the real content lives when the file is a Git LFS pointer.
+    #[serde(skip)]
+    pub lfs: Option<lfs::LfsObject>,
 }
 
-pub async fn get_repository_info(url: &str) -> Result<RepositoryInfo, RepoInfoError> {
-    let parsed_url = Url::parse(url).map_err(|e| RepoInfoError::InvalidUrl(e.to_string()))?;
+impl GitHubFile {
+    /// A plain file at `path`, as test fixtures need it.
+    #[cfg(test)]
+    pub fn fixture(path: &str, sha: &str, size: u64) -> Self {
+        Self {
+            path: path.to_string(),
+            name: path.rsplit('/').next().unwrap_or_default().to_string(),
+            file_type: "file".to_string(),
+            download_url: None,
+            git_url: None,
+            sha: sha.to_string(),
+            size,
+            lfs: None,
+        }
+    }
 
-    if parsed_url.host_str() != Some("github.com") {
-        return Err(RepoInfoError::InvalidUrl("Not a GitHub URL".to_string()));
+    pub fn is_dir(&self) -> bool {
+        self.file_type == "dir"
     }
 
-    let path_segments: Vec<&str> = parsed_url
-        .path_segments()
-        .ok_or_else(|| RepoInfoError::NotARepository("URL path is empty".to_string()))?
-        .collect();
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == "symlink"
+    }
 
-    if path_segments.len() < 2 {
-        return Err(RepoInfoError::NotARepository(
-            "URL path does not contain user and repository".to_string(),
-        ));
+    pub fn is_submodule(&self) -> bool {
+        self.file_type == "submodule"
     }
+}
 
-    let user = path_segments[0].to_string();
-    let repository = path_segments[1].to_string();
-    let (git_reference, directory) = if path_segments.len() > 3 && path_segments[2] == "tree" {
-        (Some(path_segments[3].to_string()), path_segments[4..].join("/"))
-    } else {
-        (None, path_segments[2..].join("/"))
-    };
+/// The contents API answers with an array for directories and a single object
+/// when the path is a file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ContentsResponse {
+    Directory(Vec<GitHubFile>),
+    File(Box<GitHubFile>),
+}
 
-    let repo_info = RepositoryInfo {
-        user,
-        repository,
-        git_reference,
-        directory,
-    };
+pub async fn get_repository_info(
+    url: &str,
+    enterprise_hosts: &[String],
+) -> Result<RepositoryInfo, RepoInfoError> {
+    repo_url::parse_repository_url(url, enterprise_hosts)
+}
 
-    Ok(repo_info)
+/// The REST API quota as last reported by the `X-RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// When the quota resets, in seconds since the Unix epoch.
+    pub reset: u64,
 }
 
-async fn list_files_in_dir(
-    repo_info: &RepositoryInfo,
-    token: &Option<String>,
-    path: &str,
-) -> anyhow::Result<Vec<GitHubFile>> {
-    let client = reqwest::Client::new();
-    let api_url = format!(
-        "https://api.github.com/repos/{}/{}/contents/{}",
-        repo_info.user,
-        repo_info.repository,
-        path
-    );
-
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_static("gh-dir-rust-cli"));
-    if let Some(token) = token {
-        let auth_header = format!("token {}", token);
-        headers.insert(
-            "Authorization",
-            HeaderValue::from_str(&auth_header)?,
-        );
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let number = |name: &str| headers.get(name)?.to_str().ok()?.parse().ok();
+        Some(RateLimit {
+            limit: number("x-ratelimit-limit")?,
+            remaining: number("x-ratelimit-remaining")?,
+            reset: number("x-ratelimit-reset")?,
+        })
     }
 
-    let response = client.get(&api_url).headers(headers).send().await?;
+    pub fn resets_in(&self) -> Duration {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Duration::from_secs(self.reset.saturating_sub(now))
+    }
+}
+
+impl std::fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} requests left, resets in {}",
+            self.remaining,
+            self.limit,
+            format_duration(self.resets_in())
+        )
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// Why GitHub refused a request for rate limiting, with how long to wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateLimited {
+    /// The hourly quota is used up.
+    Primary(Duration),
+    /// Too many requests in a short time; GitHub says when to retry, or else
+    /// asks for at least `SECONDARY_RATE_LIMIT_WAIT`.
+    Secondary(Duration),
+}
+
+/// How long to wait out a secondary rate limit that comes without `Retry-After`.
+const SECONDARY_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+fn is_rate_limit_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Tells rate limiting apart from other 403/429 answers by its headers, or by
+/// the message in `body` for secondary limits without `Retry-After`.
+fn rate_limited(status: reqwest::StatusCode, headers: &HeaderMap, body: &str) -> Option<RateLimited> {
+    if !is_rate_limit_status(status) {
+        return None;
+    }
+    if let Some(seconds) = headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+    {
+        return Some(RateLimited::Secondary(Duration::from_secs(seconds)));
+    }
+    match RateLimit::from_headers(headers) {
+        Some(rate_limit) if rate_limit.remaining == 0 => Some(RateLimited::Primary(rate_limit.resets_in())),
+        _ => {
+            let body = body.to_lowercase();
+            (body.contains("secondary rate limit") || body.contains("abuse detection"))
+                .then_some(RateLimited::Secondary(SECONDARY_RATE_LIMIT_WAIT))
+        }
+    }
+}
+
+/// The one HTTP client every REST API call of a run goes through, so listing
+/// reuses a single connection pool and the token is configured in one place.
+#[derive(Clone)]
+pub struct GitHubClient {
+    http: reqwest::Client,
+    /// Sleep through rate limits instead of failing.
+    wait_on_rate_limit: bool,
+    rate_limit: Arc<Mutex<Option<RateLimit>>>,
+    cache: Option<ResponseCache>,
+    /// Suspended while the client reports waiting on a rate limit.
+    progress: ProgressBar,
+}
+
+impl GitHubClient {
+    pub fn new(
+        token: &Option<String>,
+        wait_on_rate_limit: bool,
+        cache: Option<ResponseCache>,
+        progress: ProgressBar,
+    ) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("gh-dir-rust-cli"));
+        if let Some(token) = token {
+            let mut auth_header = HeaderValue::from_str(&format!("token {}", token))?;
+            auth_header.set_sensitive(true);
+            headers.insert(AUTHORIZATION, auth_header);
+        }
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .pool_max_idle_per_host(20)
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            http,
+            wait_on_rate_limit,
+            rate_limit: Arc::new(Mutex::new(None)),
+            cache,
+            progress,
+        })
+    }
+
+    /// The quota reported by the most recent API response.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        *self.rate_limit.lock().unwrap()
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.http.get(url)
+    }
+
+    /// Sends a request, keeping track of the quota and, with `--wait-on-rate-limit`,
+    /// sleeping until a rate limit is lifted and trying again. GET requests are
+    /// revalidated against the response cache.
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        let mut request = request.build()?;
+        let url = request.url().to_string();
+        let accept = request
+            .headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("*/*")
+            .to_string();
+        let cached = match &self.cache {
+            Some(cache) if request.method() == reqwest::Method::GET => cache.get(&url, &accept).await,
+            _ => None,
+        };
+        if let Some(etag) = cached.as_ref().and_then(|c| HeaderValue::from_str(&c.etag).ok()) {
+            request.headers_mut().insert(IF_NONE_MATCH, etag);
+        }
+
+        loop {
+            let retry = request.try_clone();
+            let response = self.http.execute(request).await?;
+            if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+                *self.rate_limit.lock().unwrap() = Some(rate_limit);
+            }
+            if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                if let Some(cached) = cached {
+                    return Ok(cached_response(cached));
+                }
+            }
+            if !is_rate_limit_status(response.status()) {
+                return self.store(&url, &accept, response).await;
+            }
+            // Only the body tells some secondary rate limits apart, so it is read
+            // here and put back into the response for the caller.
+            let (status, headers) = (response.status(), response.headers().clone());
+            let body = response.bytes().await?;
+            let limited = rate_limited(status, &headers, &String::from_utf8_lossy(&body));
+            let response = rebuilt_response(status, headers, body);
+            let wait = match limited {
+                Some(RateLimited::Primary(wait) | RateLimited::Secondary(wait)) => wait,
+                None => return Ok(response),
+            };
+            let Some(retry) = retry.filter(|_| self.wait_on_rate_limit) else {
+                return Ok(response);
+            };
+            self.progress.suspend(|| {
+                eprintln!(
+                    "{} waiting {} before retrying {}",
+                    console::style("Rate limited:").yellow().bold(),
+                    format_duration(wait),
+                    retry.url().path()
+                )
+            });
+            // The reset time has one-second resolution, so give it a moment extra.
+            tokio::time::sleep(wait + Duration::from_secs(1)).await;
+            request = retry;
+        }
+    }
+
+    /// Saves a successful response that carries an ETag, handing back an
+    /// equivalent one since reading the body consumes it.
+    async fn store(&self, url: &str, accept: &str, response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+        let Some(cache) = &self.cache else {
+            return Ok(response);
+        };
+        let etag = match response.headers().get(ETAG).and_then(|v| v.to_str().ok()) {
+            Some(etag) if response.status().is_success() => etag.to_string(),
+            _ => return Ok(response),
+        };
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        if let Ok(text) = std::str::from_utf8(&body) {
+            let entry = CachedResponse {
+                url: url.to_string(),
+                etag,
+                content_type: headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string),
+                body: text.to_string(),
+            };
+            // The cache only saves quota; failing to write it must not fail the run.
+            let _ = cache.put(accept, &entry).await;
+        }
+        Ok(rebuilt_response(status, headers, body))
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        let response = self.send(self.get(url)).await?;
+        Ok(check_response(response).await?.json().await?)
+    }
+}
+
+/// A response whose body has already been read, put back together.
+fn rebuilt_response(status: reqwest::StatusCode, headers: HeaderMap, body: impl Into<reqwest::Body>) -> reqwest::Response {
+    let mut rebuilt = http::Response::new(body.into());
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+    rebuilt.into()
+}
+
+/// Turns a cache entry confirmed by a `304 Not Modified` back into a response.
+fn cached_response(cached: CachedResponse) -> reqwest::Response {
+    let mut response = http::Response::new(cached.body);
+    if let Some(content_type) = cached.content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response.into()
+}
 
+/// How to list a directory.
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Maximum number of listing requests in flight at once.
+    pub concurrency: usize,
+    /// Directories this filter excludes entirely are not listed.
+    pub filter: PathFilter,
+    /// Directories this many levels below the listed one are not descended
+    /// into; 1 lists only the directory's own files.
+    pub max_depth: Option<usize>,
+}
+
+async fn check_response(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
     if !response.status().is_success() {
         let status = response.status();
+        let headers = response.headers().clone();
+        let quota = RateLimit::from_headers(&headers);
         let body = response.text().await.unwrap_or_else(|_| "Could not read response body".to_string());
-        match status {
-            reqwest::StatusCode::NOT_FOUND => {
+        let rate_limit = rate_limited(status, &headers, &body);
+        match (status, rate_limit) {
+            (_, Some(RateLimited::Primary(wait))) => {
+                anyhow::bail!(
+                    "API rate limit exceeded ({} requests per hour); it resets in {}. Provide a token with --token for a higher limit, or pass --wait-on-rate-limit.",
+                    quota.map_or(0, |q| q.limit),
+                    format_duration(wait)
+                )
+            }
+            (_, Some(RateLimited::Secondary(wait))) => {
+                anyhow::bail!(
+                    "Secondary rate limit hit: GitHub asks to wait {} before retrying. Lower --list-concurrency, or pass --wait-on-rate-limit.",
+                    format_duration(wait)
+                )
+            }
+            (reqwest::StatusCode::NOT_FOUND, _) => {
                 anyhow::bail!("Repository or directory not found (404). Please check the URL and that the resource exists.")
             }
-            reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::UNAUTHORIZED => {
-                anyhow::bail!("Permission denied (403/401). If the repository is private, please provide a valid personal access token with the --token option.")
+            (reqwest::StatusCode::UNAUTHORIZED, _) => {
+                anyhow::bail!("Bad credentials (401). Please check that the token passed with --token is valid and has not expired.")
+            }
+            (reqwest::StatusCode::FORBIDDEN, _) => {
+                anyhow::bail!("Permission denied (403). If the repository is private, please provide a valid personal access token with the --token option.")
             }
             _ => {
                 anyhow::bail!(
@@ -111,68 +495,1302 @@ async fn list_files_in_dir(
             }
         }
     }
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct RepositoryMetadata {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct GitRef {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+fn looks_like_commit_sha(candidate: &str) -> bool {
+    (4..=40).contains(&candidate.len()) && candidate.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Picks the longest run of leading `segments` that names an existing branch or
+/// tag, falling back to treating the first segment as a commit SHA. Returns how
+/// many segments belong to the ref.
+fn pick_reference(
+    segments: &[&str],
+    branches: &[String],
+    tags: &[String],
+) -> Option<(usize, RefKind)> {
+    for len in (1..=segments.len()).rev() {
+        let candidate = segments[..len].join("/");
+        if branches.contains(&candidate) {
+            return Some((len, RefKind::Branch));
+        }
+        if tags.contains(&candidate) {
+            return Some((len, RefKind::Tag));
+        }
+    }
+    segments
+        .first()
+        .filter(|first| looks_like_commit_sha(first))
+        .map(|_| (1, RefKind::Commit))
+}
+
+/// Lists the short names of refs under `refs/{namespace}/` that start with `prefix`.
+async fn matching_refs(
+    client: &GitHubClient,
+    repo_url: &str,
+    namespace: &str,
+    prefix: &str,
+) -> anyhow::Result<Vec<String>> {
+    let refs: Vec<GitRef> = client
+        .get_json(&format!("{}/git/matching-refs/{}/{}", repo_url, namespace, prefix))
+        .await?;
+    let full_prefix = format!("refs/{}/", namespace);
+    Ok(refs
+        .into_iter()
+        .filter_map(|r| r.name.strip_prefix(&full_prefix).map(str::to_string))
+        .collect())
+}
+
+/// Finds which leading `segments` name a branch, tag or commit, asking the API
+/// only for refs that start with the first segment.
+async fn classify_reference(
+    client: &GitHubClient,
+    repo_url: &str,
+    segments: &[&str],
+) -> anyhow::Result<Option<(usize, RefKind)>> {
+    let prefix = segments.first().copied().unwrap_or_default();
+    let branches = matching_refs(client, repo_url, "heads", prefix).await?;
+    let tags = matching_refs(client, repo_url, "tags", prefix).await?;
+    Ok(pick_reference(segments, &branches, &tags))
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    head: PullRequestHead,
+    base: PullRequestBase,
+}
+
+#[derive(Deserialize)]
+struct PullRequestHead {
+    #[serde(rename = "ref")]
+    name: String,
+    sha: String,
+    repo: Option<PullRequestRepository>,
+}
+
+#[derive(Deserialize)]
+struct PullRequestBase {
+    repo: Option<PullRequestRepository>,
+}
+
+#[derive(Deserialize)]
+struct PullRequestRepository {
+    full_name: String,
+}
+
+/// Resolves the ref to download (`ref_override`, the pull request head, the URL's
+/// ref, or the default branch) to a commit SHA so that every listing and download
+/// afterwards sees the same tree, even if the branch moves.
+///
+/// For `/tree/<ref>/<path>` and `/blob/<ref>/<path>` URLs the ref may itself contain slashes, so the
+/// ref/path split is decided here against the repository's real branches and
+/// tags, longest match first, and `repo_info.directory` is updated to match.
+pub async fn resolve_reference(
+    client: &GitHubClient,
+    repo_info: &mut RepositoryInfo,
+    ref_override: Option<&str>,
+) -> anyhow::Result<()> {
+    let repo_url = repo_info.repo_api_url();
+    let not_found = |reference: &str| {
+        anyhow::anyhow!(
+            "No branch, tag or commit named '{}' found in {}/{}",
+            reference,
+            repo_info.user,
+            repo_info.repository
+        )
+    };
+
+    // Even when the ref is overridden, the URL's ref still has to be split off its path.
+    let mut url_kind = None;
+    if let (true, Some(reference)) = (repo_info.reference_in_path, &repo_info.git_reference) {
+        let combined = if repo_info.directory.is_empty() {
+            reference.clone()
+        } else {
+            format!("{}/{}", reference, repo_info.directory)
+        };
+        let segments: Vec<&str> = combined.split('/').filter(|s| !s.is_empty()).collect();
+        let (len, kind) = classify_reference(client, &repo_url, &segments)
+            .await?
+            .ok_or_else(|| not_found(reference))?;
+        repo_info.directory = segments[len..].join("/");
+        repo_info.git_reference = Some(segments[..len].join("/"));
+        repo_info.reference_in_path = false;
+        url_kind = Some(kind);
+    }
+
+    let (name, kind) = match (ref_override, repo_info.pull_request, repo_info.git_reference.clone()) {
+        (Some(reference), _, _) => {
+            let (_, kind) = classify_reference(client, &repo_url, &[reference])
+                .await?
+                .ok_or_else(|| not_found(reference))?;
+            (reference.to_string(), kind)
+        }
+        (None, Some(number), _) => {
+            let pull: PullRequest = client
+                .get_json(&format!("{}/pulls/{}", repo_url, number))
+                .await
+                .map_err(|e| anyhow::anyhow!("Could not load pull request #{}: {}", number, e))?;
+            let base_repo = pull.base.repo.map(|r| r.full_name);
+            let head_repo = pull.head.repo.map(|r| r.full_name);
+            let fork = (head_repo != base_repo)
+                .then(|| head_repo.unwrap_or_else(|| "a deleted fork".to_string()));
+            // The head commit is reachable from the base repository through
+            // `refs/pull/<n>/head`, so it can be listed here even for forks.
+            let name = format!("#{} ({})", number, pull.head.name);
+            repo_info.resolved_ref = Some(ResolvedRef {
+                name,
+                kind: RefKind::PullRequest,
+                sha: pull.head.sha,
+                fork,
+            });
+            return Ok(());
+        }
+        (None, None, Some(reference)) => {
+            let kind = match url_kind {
+                Some(kind) => kind,
+                None => classify_reference(client, &repo_url, &[reference.as_str()])
+                    .await?
+                    .ok_or_else(|| not_found(&reference))?
+                    .1,
+            };
+            (reference, kind)
+        }
+        (None, None, None) => {
+            let metadata: RepositoryMetadata = client.get_json(&repo_url).await?;
+            (metadata.default_branch, RefKind::Branch)
+        }
+    };
+
+    let response = client
+        .get(&format!("{}/commits/{}", repo_url, name))
+        .header(ACCEPT, "application/vnd.github.sha");
+    let response = check_response(client.send(response).await?)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not resolve {} '{}': {}", kind, name, e))?;
+    let sha = response.text().await?.trim().to_string();
+
+    repo_info.git_reference = Some(name.clone());
+    repo_info.resolved_ref = Some(ResolvedRef { name, kind, sha, fork: None });
+    Ok(())
+}
+
+async fn list_files_in_dir(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    path: &str,
+) -> anyhow::Result<Vec<GitHubFile>> {
+    let api_url = format!("{}/contents/{}", repo_info.repo_api_url(), path);
+
+    let mut request = client.get(&api_url);
+    if let Some(reference) = repo_info.api_ref() {
+        request = request.query(&[("ref", reference)]);
+    }
+    let response = client.send(request).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(directory_not_found(&[path.to_string()], path));
+    }
+    let response = check_response(response).await?;
+
+    let mut files = match response.json().await? {
+        ContentsResponse::Directory(files) => files,
+        ContentsResponse::File(file) => vec![*file],
+    };
+    // Directory listings report submodules as "file" for backwards compatibility;
+    // they are the entries whose git_url points at a tree instead of a blob.
+    for file in &mut files {
+        if file.file_type == "file"
+            && file.download_url.is_none()
+            && file.git_url.as_deref().is_some_and(|url| url.contains("/git/trees/"))
+        {
+            file.file_type = "submodule".to_string();
+        }
+    }
+    Ok(files)
+}
+
+#[derive(Deserialize)]
+struct GitTree {
+    tree: Vec<TreeEntry>,
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    mode: String,
+    #[serde(rename = "type")]
+    entry_type: String, // "blob", "tree" or "commit"
+    sha: String,
+    size: Option<u64>,
+}
+
+impl TreeEntry {
+    fn into_github_file(self, repo_info: &RepositoryInfo, commit: &str, prefix: &str) -> anyhow::Result<GitHubFile> {
+        let path = if prefix.is_empty() {
+            self.path
+        } else {
+            format!("{}/{}", prefix, self.path)
+        };
+        let file_type = match (self.entry_type.as_str(), self.mode.as_str()) {
+            ("tree", _) => "dir",
+            ("commit", _) => "submodule",
+            (_, "120000") => "symlink",
+            _ => "file",
+        };
+        let (download_url, git_url) = match file_type {
+            "file" | "symlink" => (
+                Some(repo_info.raw_url(commit, &path)?),
+                Some(format!("{}/git/blobs/{}", repo_info.repo_api_url(), self.sha)),
+            ),
+            _ => (None, None),
+        };
+        Ok(GitHubFile {
+            name: path.rsplit('/').next().unwrap_or_default().to_string(),
+            path,
+            file_type: file_type.to_string(),
+            download_url,
+            git_url,
+            sha: self.sha,
+            size: self.size.unwrap_or(0),
+            lfs: None,
+        })
+    }
+}
+
+/// Fetches one tree object. Returns `None` when the endpoint is unusable for this
+/// repository (404, 409 or 422), so the caller can fall back to the contents API.
+async fn fetch_tree(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    tree_sha: &str,
+    recursive: bool,
+) -> anyhow::Result<Option<GitTree>> {
+    let mut request = client.get(&format!("{}/git/trees/{}", repo_info.repo_api_url(), tree_sha));
+    if recursive {
+        request = request.query(&[("recursive", "1")]);
+    }
+    let response = client.send(request).await?;
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::NOT_FOUND
+            | reqwest::StatusCode::CONFLICT
+            | reqwest::StatusCode::UNPROCESSABLE_ENTITY
+    ) {
+        return Ok(None);
+    }
+    Ok(Some(check_response(response).await?.json().await?))
+}
+
+/// A directory still to be listed, with the SHA of its tree object when known.
+#[derive(Debug, Clone)]
+struct PendingDir {
+    path: String,
+    tree_sha: Option<String>,
+    /// Levels below the directory being listed; the walker sets it for subdirectories.
+    depth: usize,
+}
+
+impl PendingDir {
+    /// Stands in for the directory's contents in a listing when it is not walked.
+    fn into_unlisted(self) -> GitHubFile {
+        GitHubFile {
+            name: self.path.rsplit('/').next().unwrap_or_default().to_string(),
+            path: self.path,
+            file_type: "dir".to_string(),
+            download_url: None,
+            git_url: None,
+            sha: self.tree_sha.unwrap_or_default(),
+            size: 0,
+            lfs: None,
+        }
+    }
+}
+
+/// Lists directories with at most `options.concurrency` requests in flight.
+/// `list_dir` returns a directory's files and the subdirectories still to visit;
+/// subdirectories excluded by `options.filter` or at `options.max_depth` are
+/// returned as "dir" entries instead of being listed. A failing directory does not cancel the others;
+/// every failure is reported at the end with the path it happened on.
+async fn walk_directories<F, Fut>(
+    roots: Vec<PendingDir>,
+    options: &ListOptions,
+    list_dir: F,
+) -> anyhow::Result<Vec<GitHubFile>>
+where
+    F: Fn(PendingDir) -> Fut,
+    Fut: Future<Output = anyhow::Result<(Vec<GitHubFile>, Vec<PendingDir>)>>,
+{
+    let mut queue: VecDeque<PendingDir> = roots.into();
+    let mut in_flight = FuturesUnordered::new();
+    let mut files = Vec::new();
+    let mut failures = Vec::new();
+
+    loop {
+        while in_flight.len() < options.concurrency.max(1) {
+            let Some(dir) = queue.pop_front() else { break };
+            let (path, depth) = (dir.path.clone(), dir.depth);
+            let listing = list_dir(dir);
+            in_flight.push(async move { (path, depth, listing.await) });
+        }
+        match in_flight.next().await {
+            Some((_, depth, Ok((dir_files, subdirs)))) => {
+                files.extend(dir_files);
+                for mut subdir in subdirs {
+                    subdir.depth = depth + 1;
+                    if options.filter.excludes_dir(&subdir.path)
+                        || options.max_depth.is_some_and(|max| subdir.depth >= max)
+                    {
+                        files.push(subdir.into_unlisted());
+                    } else {
+                        queue.push_back(subdir);
+                    }
+                }
+            }
+            Some((path, depth, Err(e))) => failures.push((path, depth, e)),
+            None => break,
+        }
+    }
 
-    let files: Vec<GitHubFile> = response.json().await?;
+    // A requested directory that does not exist is reported as such, so callers
+    // can tell it apart from listings that failed.
+    if let Some(i) = failures.iter().position(|(_, depth, e)| *depth == 0 && e.is::<PathNotFound>()) {
+        return Err(failures.swap_remove(i).2);
+    }
+    if !failures.is_empty() {
+        let details: Vec<String> = failures
+            .iter()
+            .map(|(path, _, e)| format!("  {}: {}", if path.is_empty() { "/" } else { path }, e))
+            .collect();
+        anyhow::bail!(
+            "Failed to list {} director{}:\n{}",
+            failures.len(),
+            if failures.len() == 1 { "y" } else { "ies" },
+            details.join("\n")
+        );
+    }
     Ok(files)
 }
 
+/// Lists every file below the tree `tree_sha` (whose path is `prefix`). A
+/// truncated recursive response is discarded and the tree is walked one level
+/// at a time instead, each subtree again trying a single recursive call first.
+async fn walk_tree(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    options: &ListOptions,
+    commit: &str,
+    tree_sha: String,
+    prefix: String,
+) -> anyhow::Result<Vec<GitHubFile>> {
+    let root = PendingDir {
+        path: prefix,
+        tree_sha: Some(tree_sha),
+        depth: 0,
+    };
+    walk_directories(vec![root], options, |dir| async move {
+        let tree_sha = dir.tree_sha.as_deref().unwrap_or(commit);
+        let not_found = || anyhow::anyhow!("Tree {} not found", tree_sha);
+        // With a depth limit, a recursive call would fetch the levels that are
+        // never descended into, so go one level at a time from the start.
+        let recursive = match options.max_depth {
+            Some(_) => None,
+            None => Some(
+                fetch_tree(client, repo_info, tree_sha, true)
+                    .await?
+                    .ok_or_else(not_found)?,
+            ),
+        };
+        let mut subdirs = Vec::new();
+        let tree = if let Some(tree) = recursive.filter(|tree| !tree.truncated) {
+            tree
+        } else {
+            let shallow = fetch_tree(client, repo_info, tree_sha, false)
+                .await?
+                .ok_or_else(not_found)?;
+            for entry in shallow.tree.iter().filter(|e| e.entry_type == "tree") {
+                subdirs.push(PendingDir {
+                    path: join_path(&dir.path, &entry.path),
+                    tree_sha: Some(entry.sha.clone()),
+                    depth: 0,
+                });
+            }
+            shallow
+        };
+        let files = tree
+            .tree
+            .into_iter()
+            .filter(|entry| entry.entry_type != "tree")
+            .map(|entry| entry.into_github_file(repo_info, commit, &dir.path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((files, subdirs))
+    })
+    .await
+}
+
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Lists the requested directories (or single files) with the Git Trees API:
+/// one recursive request for the whole commit, filtered to `directories`.
+/// Returns `None` if the trees API cannot be used here.
+async fn list_with_trees(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    options: &ListOptions,
+) -> anyhow::Result<Option<Vec<GitHubFile>>> {
+    let Some(commit) = repo_info.api_ref() else {
+        return Ok(None);
+    };
+    let Some(root) = fetch_tree(client, repo_info, commit, true).await? else {
+        return Ok(None);
+    };
+
+    // The whole listing already came in one request, so a depth limit saves no
+    // calls here; entries past it are dropped and the directories at the limit
+    // reported as not descended into.
+    if !root.truncated {
+        let mut files = Vec::new();
+        let mut found: Vec<bool> = directories.iter().map(|d| d.is_empty()).collect();
+        for entry in root.tree {
+            let Some(i) = directories
+                .iter()
+                .position(|d| entry.path == *d || is_below(d, &entry.path))
+            else {
+                continue;
+            };
+            found[i] = true;
+            if entry.path == directories[i] {
+                if entry.entry_type != "tree" {
+                    files.push(entry.into_github_file(repo_info, commit, "")?);
+                }
+                continue;
+            }
+            let depth = depth_below(&directories[i], &entry.path);
+            match options.max_depth {
+                Some(max) if entry.entry_type == "tree" && depth == max => {
+                    let dir = PendingDir {
+                        path: entry.path,
+                        tree_sha: Some(entry.sha),
+                        depth,
+                    };
+                    files.push(dir.into_unlisted());
+                }
+                Some(max) if depth > max => {}
+                _ if entry.entry_type != "tree" => {
+                    files.push(entry.into_github_file(repo_info, commit, "")?);
+                }
+                _ => {}
+            }
+        }
+        if let Some(i) = found.iter().position(|found| !found) {
+            return Err(directory_not_found(directories, &directories[i]));
+        }
+        return Ok(Some(files));
+    }
+
+    let mut files = Vec::new();
+    for directory in directories {
+        files.extend(walk_tree_directory(client, repo_info, options, commit, directory).await?);
+    }
+    Ok(Some(files))
+}
+
+/// Lists `directory` when the recursive tree of the whole commit is too big for
+/// one response: descends to it one level at a time, then walks its subtrees.
+async fn walk_tree_directory(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    options: &ListOptions,
+    commit: &str,
+    directory: &str,
+) -> anyhow::Result<Vec<GitHubFile>> {
+    let not_found = || directory_not_found(&[directory.to_string()], directory);
+    let mut tree_sha = commit.to_string();
+    let mut prefix = String::new();
+    for component in directory.split('/').filter(|c| !c.is_empty()) {
+        let tree = fetch_tree(client, repo_info, &tree_sha, false)
+            .await?
+            .ok_or_else(not_found)?;
+        let entry = tree
+            .tree
+            .into_iter()
+            .find(|e| e.path == component)
+            .ok_or_else(not_found)?;
+        if entry.entry_type != "tree" {
+            return Ok(vec![entry.into_github_file(repo_info, commit, &prefix)?]);
+        }
+        tree_sha = entry.sha;
+        prefix = join_path(&prefix, component);
+    }
+
+    walk_tree(client, repo_info, options, commit, tree_sha, prefix).await
+}
+
+/// The error for a requested path that does not exist, naming it when several were requested.
+fn directory_not_found(directories: &[String], directory: &str) -> anyhow::Error {
+    let message = if directories.len() > 1 {
+        format!("'{}' not found (404). Please check the path and that it exists at this ref.", directory)
+    } else {
+        "Repository or directory not found (404). Please check the URL and that the resource exists.".to_string()
+    };
+    PathNotFound(message).into()
+}
+
+/// A requested path that does not exist at the listed commit.
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct PathNotFound(String);
+
+/// Whether `path` is inside `directory`; everything is inside the root, `""`.
+fn is_below(directory: &str, path: &str) -> bool {
+    directory.is_empty() || path.strip_prefix(directory).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Drops the requested paths that another one already covers, so that
+/// overlapping paths are listed once.
+pub fn covering_directories(paths: &[String]) -> Vec<String> {
+    let mut directories: Vec<String> = paths.iter().map(|p| p.trim_matches('/').to_string()).collect();
+    directories.sort();
+    directories.dedup();
+    let covered = |d: &String| directories.iter().any(|other| other != d && is_below(other, d));
+    directories.iter().filter(|d| !covered(d)).cloned().collect()
+}
+
+/// How many levels below `directory` the entry at `path` is: 1 for its own files.
+fn depth_below(directory: &str, path: &str) -> usize {
+    let directory = directory.trim_matches('/');
+    let relative = if directory.is_empty() {
+        path
+    } else {
+        path.strip_prefix(directory).unwrap_or(path)
+    };
+    relative.split('/').filter(|c| !c.is_empty()).count()
+}
+
+/// Orders files the way a directory-by-directory walk lists them: at every
+/// level, files come before subdirectories, each sorted by name.
+fn sort_files(files: &mut [GitHubFile]) {
+    files.sort_by(|a, b| {
+        let key = |path: &str| {
+            let components: Vec<&str> = path.split('/').collect();
+            let last = components.len() - 1;
+            components
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| (i != last, c.to_string()))
+                .collect::<Vec<_>>()
+        };
+        key(&a.path).cmp(&key(&b.path))
+    });
+}
+
+/// The result of listing a directory.
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub files: Vec<GitHubFile>,
+    /// Things the user should know about how the listing was obtained.
+    pub warnings: Vec<String>,
+}
+
+/// Lists every file in `directories` (paths in the repository, each a directory
+/// or a single file) together; paths that overlap are listed once.
 pub async fn get_all_files(
+    client: &GitHubClient,
     repo_info: &RepositoryInfo,
-    token: &Option<String>,
-) -> anyhow::Result<(Vec<GitHubFile>, u64)> {
-    let mut all_files = Vec::new();
-    let mut total_size = 0;
-    let mut dirs_to_visit = vec![repo_info.directory.clone()];
+    directories: &[String],
+    options: &ListOptions,
+) -> anyhow::Result<Listing> {
+    let directories = covering_directories(directories);
+    let warnings = Mutex::new(Vec::new());
+    let mut files = match list_with_trees(client, repo_info, &directories, options).await? {
+        Some(files) => files,
+        None => get_all_files_from_contents(client, repo_info, &directories, options, &warnings).await?,
+    };
+    sort_files(&mut files);
+    files.dedup_by(|a, b| a.path == b.path);
+    Ok(Listing {
+        files,
+        warnings: warnings.into_inner().unwrap_or_default(),
+    })
+}
 
-    while let Some(dir) = dirs_to_visit.pop() {
-        let files = list_files_in_dir(repo_info, token, &dir).await?;
+/// The contents API returns at most this many entries for a directory and
+/// silently drops the rest. Large directories are covered by the trees API,
+/// which has no such cap; the contents API is only used without it, so a
+/// directory that hits the limit can only be warned about.
+const CONTENTS_LISTING_LIMIT: usize = 1000;
 
-        let mut dir_futures = Vec::new();
+/// Directory-by-directory listing through the contents API, used when the
+/// trees API is not available.
+async fn get_all_files_from_contents(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    options: &ListOptions,
+    warnings: &Mutex<Vec<String>>,
+) -> anyhow::Result<Vec<GitHubFile>> {
+    let roots = directories
+        .iter()
+        .map(|directory| PendingDir {
+            path: directory.clone(),
+            tree_sha: None,
+            depth: 0,
+        })
+        .collect();
+    walk_directories(roots, options, |dir| async move {
+        let listing = list_files_in_dir(client, repo_info, &dir.path).await?;
+        if listing.len() >= CONTENTS_LISTING_LIMIT {
+            warn(
+                warnings,
+                format!(
+                    "'{}' has {} or more entries and the Git Trees API is unavailable; some files may be missing.",
+                    dir.path, CONTENTS_LISTING_LIMIT
+                ),
+            );
+        }
 
-        for file in files {
+        let mut files = Vec::new();
+        let mut subdirs = Vec::new();
+        for file in listing {
             if file.file_type == "dir" {
-                dir_futures.push(get_all_files_recursive(repo_info.clone(), token.clone(), file.path));
+                subdirs.push(PendingDir {
+                    path: file.path,
+                    tree_sha: Some(file.sha),
+                    depth: 0,
+                });
             } else {
-                total_size += file.size;
-                all_files.push(file);
+                files.push(file);
             }
         }
+        Ok((files, subdirs))
+    })
+    .await
+}
 
-        let sub_dir_files = try_join_all(dir_futures).await?;
-        for (sub_files, sub_size) in sub_dir_files {
-            all_files.extend(sub_files);
-            total_size += sub_size;
-        }
+fn warn(warnings: &Mutex<Vec<String>>, message: String) {
+    if let Ok(mut warnings) = warnings.lock() {
+        warnings.push(message);
     }
+}
+
+/// Media type that makes the contents and blobs APIs return raw file content.
+pub(crate) const RAW_MEDIA_TYPE: &str = "application/vnd.github.raw";
 
-    Ok((all_files, total_size))
+/// Reads a text file at the pinned commit through the contents API, or `None`
+/// if there is no such file.
+pub async fn read_file(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    path: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut request = client
+        .get(&format!("{}/contents/{}", repo_info.repo_api_url(), path))
+        .header(ACCEPT, RAW_MEDIA_TYPE);
+    if let Some(reference) = repo_info.api_ref() {
+        request = request.query(&[("ref", reference)]);
+    }
+    let response = client.send(request).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(check_response(response).await?.text().await?))
 }
 
-async fn get_all_files_recursive(
-    repo_info: RepositoryInfo,
-    token: Option<String>,
-    path: String,
-) -> anyhow::Result<(Vec<GitHubFile>, u64)> {
-    let mut all_files = Vec::new();
-    let mut total_size = 0;
-    let files = list_files_in_dir(&repo_info, &token, &path).await?;
+/// Fetches the content of a listed file with the API client, for the few small
+/// files that have to be looked at before anything is downloaded.
+pub async fn fetch_file(client: &GitHubClient, file: &GitHubFile) -> anyhow::Result<Vec<u8>> {
+    let request = match (&file.download_url, &file.git_url) {
+        (Some(download_url), _) => client.get(download_url),
+        (None, Some(git_url)) => client.get(git_url).header(ACCEPT, RAW_MEDIA_TYPE),
+        (None, None) => anyhow::bail!("No download URL for {}", file.path),
+    };
+    let response = check_response(client.send(request).await?).await?;
+    Ok(response.bytes().await?.into())
+}
+
+/// Parses `.gitmodules` into `(path, url)` pairs.
+fn parse_gitmodules(content: &str) -> Vec<(String, String)> {
+    let mut modules = Vec::new();
+    let (mut path, mut url) = (None, None);
+    let mut flush = |path: &mut Option<String>, url: &mut Option<String>| {
+        if let (Some(p), Some(u)) = (path.take(), url.take()) {
+            modules.push((p, u));
+        }
+    };
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            flush(&mut path, &mut url);
+        } else if let Some((key, value)) = line.split_once('=') {
+            match key.trim() {
+                "path" => path = Some(value.trim().trim_end_matches('/').to_string()),
+                "url" => url = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    flush(&mut path, &mut url);
+    modules
+}
+
+/// Turns a submodule URL from `.gitmodules` into the repository it points at.
+/// Relative URLs (`../other.git`) are relative to the superproject. Submodules
+/// have to be on the superproject's host: they are listed and downloaded with
+/// its token, which must not be sent anywhere else.
+fn submodule_repository(repo_info: &RepositoryInfo, url: &str) -> Result<RepositoryInfo, RepoInfoError> {
+    let url = if url.starts_with("./") || url.starts_with("../") {
+        let base = format!("https://{}/{}/{}/", repo_info.host, repo_info.user, repo_info.repository);
+        url::Url::parse(&base)
+            .and_then(|base| base.join(url))
+            .map_err(|e| RepoInfoError::InvalidUrl(e.to_string()))?
+            .to_string()
+    } else {
+        url.to_string()
+    };
+    let mut submodule = repo_url::parse_repository_url(&url, std::slice::from_ref(&repo_info.host))?;
+    if !submodule.host.eq_ignore_ascii_case(&repo_info.host) {
+        return Err(RepoInfoError::OtherHost {
+            host: submodule.host,
+            superproject: repo_info.host.clone(),
+        });
+    }
+    submodule.api_base = repo_info.api_base.clone();
+    Ok(submodule)
+}
 
-    let mut dir_futures = Vec::new();
+/// Replaces every submodule entry in `files` with the files of the submodule at
+/// the commit the superproject records, recursing into nested submodules.
+pub async fn expand_submodules(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    files: Vec<GitHubFile>,
+    options: &ListOptions,
+) -> anyhow::Result<Vec<GitHubFile>> {
+    if !files.iter().any(GitHubFile::is_submodule) {
+        return Ok(files);
+    }
+    let gitmodules = read_file(client, repo_info, ".gitmodules")
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not read .gitmodules: {}", e))?
+        .ok_or_else(|| anyhow::anyhow!("Could not read .gitmodules: it does not exist"))?;
+    let modules = parse_gitmodules(&gitmodules);
 
+    // Paths inside a submodule are relative to its own root, so the filter is
+    // applied to them once they have been prefixed.
+    let submodule_options = ListOptions {
+        filter: PathFilter::default(),
+        ..options.clone()
+    };
+    let mut expanded = Vec::new();
     for file in files {
-        if file.file_type == "dir" {
-            dir_futures.push(get_all_files_recursive(repo_info.clone(), token.clone(), file.path));
+        if !file.is_submodule() || options.filter.excludes_dir(&file.path) {
+            expanded.push(file);
+            continue;
+        }
+        // A submodule's contents count towards the depth limit like any other
+        // directory's; one right at the limit is not descended into.
+        let directory = directories
+            .iter()
+            .filter(|d| is_below(d, &file.path))
+            .max_by_key(|d| d.len())
+            .map_or("", String::as_str);
+        let depth = depth_below(directory, &file.path);
+        let max_depth = options.max_depth.map(|max| max.saturating_sub(depth));
+        if max_depth == Some(0) {
+            let dir = PendingDir {
+                path: file.path,
+                tree_sha: Some(file.sha),
+                depth,
+            };
+            expanded.push(dir.into_unlisted());
+            continue;
+        }
+        let (_, url) = modules
+            .iter()
+            .find(|(path, _)| *path == file.path)
+            .ok_or_else(|| anyhow::anyhow!("Submodule '{}' is missing from .gitmodules", file.path))?;
+        let mut submodule = submodule_repository(repo_info, url)
+            .map_err(|e| anyhow::anyhow!("Submodule '{}' ({}): {}", file.path, url, e))?;
+        submodule.resolved_ref = Some(ResolvedRef {
+            name: file.sha.clone(),
+            kind: RefKind::Commit,
+            sha: file.sha.clone(),
+            fork: None,
+        });
+
+        let submodule_options = ListOptions {
+            max_depth,
+            ..submodule_options.clone()
+        };
+        let root = [String::new()];
+        let listing = Box::pin(get_all_files(client, &submodule, &root, &submodule_options))
+            .await
+            .map_err(|e| anyhow::anyhow!("Submodule '{}': {}", file.path, e))?;
+        let nested =
+            Box::pin(expand_submodules(client, &submodule, &root, listing.files, &submodule_options)).await?;
+        expanded.extend(nested.into_iter().map(|mut nested_file| {
+            nested_file.path = join_path(&file.path, &nested_file.path);
+            nested_file
+        }));
+    }
+    sort_files(&mut expanded);
+    Ok(expanded)
+}
+
+/// The compare API lists at most this many changed files.
+const COMPARE_FILES_LIMIT: usize = 300;
+
+#[derive(Debug, Deserialize)]
+struct Comparison {
+    /// `ahead`, `behind`, `identical` or `diverged`.
+    status: String,
+    merge_base_commit: CommitSummary,
+    #[serde(default)]
+    files: Vec<ComparedFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitSummary {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComparedFile {
+    filename: String,
+    status: String,
+    previous_filename: Option<String>,
+}
+
+/// How the files in the listed directories changed since an earlier commit.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Files added or modified, including the new paths of renamed files.
+    pub changed: HashSet<String>,
+    pub removed: Vec<String>,
+    /// `(old path, new path)` of renamed files.
+    pub renamed: Vec<(String, String)>,
+    pub warnings: Vec<String>,
+}
+
+impl Comparison {
+    /// Why the compared files are not all the changes from `base`, if they are
+    /// not: the compare API diffs against the merge base, which is `base` only
+    /// when the newer commit descends from it, and lists a limited number of files.
+    fn incomplete(&self, base: &str) -> Option<String> {
+        if self.status == "diverged" || self.merge_base_commit.sha != base {
+            Some("The compared refs have diverged, so the compare API only shows the changes since their merge base; both refs were listed and compared instead".to_string())
+        } else if self.files.len() >= COMPARE_FILES_LIMIT {
+            Some(format!(
+                "The compare API lists at most {} changed files, so both refs were listed and compared instead",
+                COMPARE_FILES_LIMIT
+            ))
         } else {
-            total_size += file.size;
-            all_files.push(file);
+            None
         }
     }
 
-    let sub_dir_files = try_join_all(dir_futures).await?;
-    for (sub_files, sub_size) in sub_dir_files {
-        all_files.extend(sub_files);
-        total_size += sub_size;
+    /// The changes to files `listed` accepts, from a complete comparison.
+    fn changes(self, listed: impl Fn(&str) -> bool) -> Changes {
+        let mut changes = Changes::default();
+        for file in self.files {
+            match (file.status.as_str(), file.previous_filename) {
+                ("removed", _) if listed(&file.filename) => changes.removed.push(file.filename),
+                ("removed", _) => {}
+                ("renamed", Some(previous)) if listed(&previous) => {
+                    changes.changed.insert(file.filename.clone());
+                    changes.renamed.push((previous, file.filename));
+                }
+                _ => {
+                    changes.changed.insert(file.filename);
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Finds what changed in `directories` between the commit `base` and the one
+/// `repo_info` is pinned to, with the compare API. `files` is the listing at
+/// the newer commit. When the comparison cannot be used, because the compare
+/// API's file limit is hit or the refs have diverged, the directories are
+/// listed at `base` as well and the listings compared by blob SHA instead,
+/// which cannot tell a rename from a removal and an addition.
+pub async fn changes_since(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    base: &str,
+    files: &[GitHubFile],
+    options: &ListOptions,
+) -> anyhow::Result<Changes> {
+    let head = repo_info.api_ref().expect("the ref is resolved before comparing");
+    let comparison: Comparison = client
+        .get_json(&format!("{}/compare/{}...{}", repo_info.repo_api_url(), base, head))
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not compare {} with {}: {}", base, head, e))?;
+    let directories = covering_directories(directories);
+    let listed = |path: &str| {
+        options.filter.matches(path) && directories.iter().any(|d| d == path || is_below(d, path))
+    };
+
+    let Some(reason) = comparison.incomplete(base) else {
+        return Ok(comparison.changes(listed));
+    };
+
+    let mut base_info = repo_info.clone();
+    base_info.resolved_ref = Some(ResolvedRef {
+        name: base.to_string(),
+        kind: RefKind::Commit,
+        sha: base.to_string(),
+        fork: None,
+    });
+    let old = match get_all_files(client, &base_info, &directories, options).await {
+        Ok(listing) => listing.files,
+        Err(e) if e.is::<PathNotFound>() => {
+            // Directories added since `base` are listed as empty there, so
+            // everything in them counts as added.
+            let mut old = Vec::new();
+            for directory in &directories {
+                match get_all_files(client, &base_info, std::slice::from_ref(directory), options).await {
+                    Ok(listing) => old.extend(listing.files),
+                    Err(e) if e.is::<PathNotFound>() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            old
+        }
+        Err(e) => return Err(e),
+    };
+    let mut changes = compare_listings(&old, files, listed);
+    changes.warnings.push(format!("{}; renamed files show up as removed and added.", reason));
+    Ok(changes)
+}
+
+/// The changes between the listing `old` at the earlier commit and `files` at
+/// the newer one, found by blob SHA; removals are limited to files `listed` accepts.
+fn compare_listings(old: &[GitHubFile], files: &[GitHubFile], listed: impl Fn(&str) -> bool) -> Changes {
+    let mut changes = Changes::default();
+    let old_shas: HashMap<&str, &str> = old
+        .iter()
+        .filter(|f| !f.is_dir())
+        .map(|f| (f.path.as_str(), f.sha.as_str()))
+        .collect();
+    for file in files.iter().filter(|f| !f.is_dir()) {
+        if old_shas.get(file.path.as_str()) != Some(&file.sha.as_str()) {
+            changes.changed.insert(file.path.clone());
+        }
+    }
+    let current: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+    changes.removed = old
+        .iter()
+        .filter(|f| !f.is_dir() && listed(&f.path) && !current.contains(f.path.as_str()))
+        .map(|f| f.path.clone())
+        .collect();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pick_reference_prefers_longest_match() {
+        let branches = names(&["feature", "feature/new-parser"]);
+        let segments = ["feature", "new-parser", "src"];
+        assert_eq!(pick_reference(&segments, &branches, &[]), Some((2, RefKind::Branch)));
+    }
+
+    #[test]
+    fn test_pick_reference_tags_and_commits() {
+        let tags = names(&["release/v1.2"]);
+        assert_eq!(
+            pick_reference(&["release", "v1.2", "docs"], &[], &tags),
+            Some((2, RefKind::Tag))
+        );
+        assert_eq!(
+            pick_reference(&["1a2b3c4", "src"], &[], &[]),
+            Some((1, RefKind::Commit))
+        );
+        assert_eq!(pick_reference(&["missing", "src"], &names(&["main"]), &tags), None);
+    }
+
+    #[test]
+    fn test_sort_files_lists_files_before_subdirectories() {
+        let mut files: Vec<_> = ["src/lib/a.rs", "src/main.rs", "src/tests/t.rs", "src/build.rs"]
+            .into_iter()
+            .map(|path| GitHubFile::fixture(path, "", 0))
+            .collect();
+        sort_files(&mut files);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["src/build.rs", "src/main.rs", "src/lib/a.rs", "src/tests/t.rs"]);
+    }
+
+    #[test]
+    fn test_tree_entry_types_and_raw_urls() {
+        let info = parse("https://github.com/user/repo/tree/main/my dir");
+        let entry = |path: &str, mode: &str, entry_type: &str| TreeEntry {
+            path: path.to_string(),
+            mode: mode.to_string(),
+            entry_type: entry_type.to_string(),
+            sha: "abc".to_string(),
+            size: Some(3),
+        };
+
+        let file = entry("a #1.txt", "100644", "blob").into_github_file(&info, "c0ffee", "my dir").unwrap();
+        assert_eq!(file.path, "my dir/a #1.txt");
+        assert_eq!(file.file_type, "file");
+        assert_eq!(
+            file.download_url.as_deref(),
+            Some("https://raw.githubusercontent.com/user/repo/c0ffee/my%20dir/a%20%231.txt")
+        );
+
+        let link = entry("link", "120000", "blob").into_github_file(&info, "c0ffee", "").unwrap();
+        assert_eq!(link.file_type, "symlink");
+        assert_eq!(
+            link.git_url.as_deref(),
+            Some("https://api.github.com/repos/user/repo/git/blobs/abc")
+        );
+        let module = entry("vendor/lib", "160000", "commit").into_github_file(&info, "c0ffee", "").unwrap();
+        assert_eq!(module.file_type, "submodule");
+        assert!(module.download_url.is_none());
+    }
+
+    fn parse(url: &str) -> RepositoryInfo {
+        repo_url::parse_repository_url(url, &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_walk_directories_is_bounded_and_reports_failed_paths() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let dir = |path: String| PendingDir { path, tree_sha: None, depth: 0 };
+        let roots = (0..10).map(|i| dir(format!("d{}", i))).collect();
+
+        let options = ListOptions {
+            concurrency: 3,
+            filter: PathFilter::default(),
+            max_depth: None,
+        };
+        let result = walk_directories(roots, &options, |pending| {
+            let (in_flight, peak) = (&in_flight, &peak);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                match pending.path.as_str() {
+                    "d4" => anyhow::bail!("boom"),
+                    "d5" => Ok((vec![], vec![dir("d5/sub".to_string())])),
+                    path => Ok((vec![GitHubFile::fixture(&format!("{}/f", path), "", 0)], vec![])),
+                }
+            }
+        })
+        .await;
+
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("Failed to list 1 directory"), "{}", message);
+        assert!(message.contains("d4: boom"), "{}", message);
     }
 
-    Ok((all_files, total_size))
-}
\ No newline at end of file
+    #[test]
+    fn test_covering_directories_drops_overlaps() {
+        assert_eq!(
+            covering_directories(&names(&["proto/v1", "schemas/", "proto", "LICENSE", "protos", "schemas"])),
+            names(&["LICENSE", "proto", "protos", "schemas"])
+        );
+        assert_eq!(covering_directories(&names(&["docs", ""])), names(&[""]));
+    }
+
+    #[test]
+    fn test_comparison_changes_and_fallbacks() {
+        let base = "a".repeat(40);
+        let comparison = |status: &str, merge_base: &str| -> Comparison {
+            serde_json::from_value(serde_json::json!({
+                "status": status,
+                "merge_base_commit": { "sha": merge_base },
+                "files": [
+                    { "filename": "docs/guide.md", "status": "modified" },
+                    { "filename": "docs/old.md", "status": "removed" },
+                    { "filename": "src/lib.rs", "status": "removed" },
+                    { "filename": "docs/new.md", "status": "renamed", "previous_filename": "docs/draft.md" },
+                    { "filename": "docs/moved.md", "status": "renamed", "previous_filename": "notes/moved.md" }
+                ]
+            }))
+            .unwrap()
+        };
+
+        let ahead = comparison("ahead", &base);
+        assert_eq!(ahead.incomplete(&base), None);
+        let changes = ahead.changes(|path| path.starts_with("docs/"));
+        assert_eq!(changes.removed, ["docs/old.md"]);
+        assert_eq!(changes.renamed, [("docs/draft.md".to_string(), "docs/new.md".to_string())]);
+        let mut changed: Vec<_> = changes.changed.into_iter().collect();
+        changed.sort();
+        assert_eq!(changed, ["docs/guide.md", "docs/moved.md", "docs/new.md"]);
+
+        // Diffs against a merge base that is not `base` miss changes made on its side.
+        assert!(comparison("diverged", &"c".repeat(40)).incomplete(&base).is_some());
+        assert!(comparison("behind", &"c".repeat(40)).incomplete(&base).is_some());
+
+        // Listings compared instead; `proto` did not exist at `base`.
+        let old = [GitHubFile::fixture("docs/guide.md", "1", 0), GitHubFile::fixture("docs/old.md", "2", 0)];
+        let files = [
+            GitHubFile::fixture("docs/guide.md", "3", 0),
+            GitHubFile::fixture("proto/a.proto", "4", 0),
+        ];
+        let changes = compare_listings(&old, &files, |path| path.starts_with("docs/") || path.starts_with("proto/"));
+        let mut changed: Vec<_> = changes.changed.into_iter().collect();
+        changed.sort();
+        assert_eq!(changed, ["docs/guide.md", "proto/a.proto"]);
+        assert_eq!(changes.removed, ["docs/old.md"]);
+        assert!(directory_not_found(&names(&["docs", "proto"]), "proto").is::<PathNotFound>());
+    }
+
+    #[tokio::test]
+    async fn test_walk_directories_stops_at_max_depth() {
+        let options = ListOptions {
+            concurrency: 2,
+            filter: PathFilter::default(),
+            max_depth: Some(2),
+        };
+        let root = PendingDir { path: "api".to_string(), tree_sha: None, depth: 0 };
+        let listed = Mutex::new(Vec::new());
+        let files = walk_directories(vec![root], &options, |pending| {
+            let listed = &listed;
+            async move {
+                listed.lock().unwrap().push(pending.path.clone());
+                let sub = PendingDir { path: format!("{}/sub", pending.path), tree_sha: None, depth: 0 };
+                Ok((vec![GitHubFile::fixture(&format!("{}/f", pending.path), "", 0)], vec![sub]))
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(*listed.lock().unwrap(), names(&["api", "api/sub"]));
+        let unlisted: Vec<_> = files.iter().filter(|f| f.is_dir()).map(|f| f.path.as_str()).collect();
+        assert_eq!(unlisted, ["api/sub/sub"]);
+        assert_eq!(depth_below("api", "api/sub/sub"), 2);
+    }
+
+    #[test]
+    fn test_rate_limits_are_told_apart_from_auth_failures() {
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            map
+        };
+        let forbidden = reqwest::StatusCode::FORBIDDEN;
+
+        let exhausted = headers(&[
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "0"),
+        ]);
+        assert_eq!(rate_limited(forbidden, &exhausted, ""), Some(RateLimited::Primary(Duration::ZERO)));
+
+        let retry_after = headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "4000")]);
+        assert_eq!(
+            rate_limited(reqwest::StatusCode::TOO_MANY_REQUESTS, &retry_after, ""),
+            Some(RateLimited::Secondary(Duration::from_secs(30)))
+        );
+
+        let permission_denied = headers(&[
+            ("x-ratelimit-limit", "5000"),
+            ("x-ratelimit-remaining", "4999"),
+            ("x-ratelimit-reset", "0"),
+        ]);
+        let denied_body = r#"{"message":"Resource not accessible by integration"}"#;
+        assert_eq!(rate_limited(forbidden, &permission_denied, denied_body), None);
+        assert_eq!(rate_limited(reqwest::StatusCode::OK, &exhausted, ""), None);
+
+        // Secondary limits do not always say when to retry.
+        let secondary_body = r#"{"message":"You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#;
+        assert_eq!(
+            rate_limited(forbidden, &permission_denied, secondary_body),
+            Some(RateLimited::Secondary(SECONDARY_RATE_LIMIT_WAIT))
+        );
+
+        let quota = RateLimit::from_headers(&permission_denied).unwrap();
+        assert_eq!((quota.limit, quota.remaining), (5000, 4999));
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+    }
+
+    #[test]
+    fn test_gitmodules_and_relative_submodule_urls() {
+        let modules = parse_gitmodules(
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = ../lib.git\n[submodule \"ext\"]\n\tpath = ext/\n\turl = git@github.com:other/ext.git\n",
+        );
+        assert_eq!(
+            modules,
+            vec![
+                ("vendor/lib".to_string(), "../lib.git".to_string()),
+                ("ext".to_string(), "git@github.com:other/ext.git".to_string()),
+            ]
+        );
+
+        let info = parse("https://github.com/user/repo");
+        let lib = submodule_repository(&info, "../lib.git").unwrap();
+        assert_eq!((lib.user.as_str(), lib.repository.as_str()), ("user", "lib"));
+        let ext = submodule_repository(&info, "git@github.com:other/ext.git").unwrap();
+        assert_eq!((ext.user.as_str(), ext.repository.as_str()), ("other", "ext"));
+    }
+
+    #[test]
+    fn test_submodule_on_another_host_is_rejected() {
+        let hosts = ["ghe.example.com".to_string()];
+        let mut info = repo_url::parse_repository_url("https://ghe.example.com/team/repo", &hosts).unwrap();
+        info.api_base = "https://ghe.example.com/api/v3".to_string();
+
+        let lib = submodule_repository(&info, "../lib.git").unwrap();
+        assert_eq!((lib.host.as_str(), lib.api_base.as_str()), ("ghe.example.com", "https://ghe.example.com/api/v3"));
+        let error = submodule_repository(&info, "https://github.com/other/ext.git").unwrap_err();
+        assert!(matches!(error, RepoInfoError::OtherHost { ref host, .. } if host == "github.com"));
+        assert!(error.to_string().contains("on github.com, not ghe.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_blob_url_is_parsed_like_tree_url() {
+        let info = get_repository_info("https://github.com/user/repo/blob/main/src/lib.rs", &[])
+            .await
+            .unwrap();
+        assert_eq!(info.git_reference.as_deref(), Some("main"));
+        assert_eq!(info.directory, "src/lib.rs");
+        assert!(info.reference_in_path);
+    }
+
+    #[test]
+    fn test_contents_response_accepts_single_file() {
+        let body = r#"{"path":"src/lib.rs","name":"lib.rs","type":"file","download_url":"https://raw.githubusercontent.com/user/repo/main/src/lib.rs","sha":"abc","size":12}"#;
+        match serde_json::from_str::<ContentsResponse>(body).unwrap() {
+            ContentsResponse::File(file) => assert_eq!(file.path, "src/lib.rs"),
+            ContentsResponse::Directory(_) => panic!("expected a single file"),
+        }
+    }
+}
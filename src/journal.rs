@@ -0,0 +1,245 @@
+use crate::github::GitHubFile;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the journal in the output directory.
+pub const JOURNAL_NAME: &str = ".gh-dir-journal";
+
+/// What a download is of, recorded on the journal's first line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// `host/user/repo`
+    pub repository: String,
+    pub commit: String,
+}
+
+/// Every further line: a file that was written completely, or the temporary
+/// file a download is being written to, relative to the output directory.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Completed { path: String, sha: String },
+    Partial { partial: String },
+}
+
+/// Record of a download into a directory, kept so that a run that crashes, loses
+/// the network or is interrupted can be continued by the next one. The journal
+/// is a JSON line naming the session followed by a line per file, appended as
+/// downloads start and finish, and is deleted once everything is downloaded.
+/// The temporary files it lists are left for the next run to continue only as
+/// long as the journal lives: they are deleted along with it, or when a
+/// download at another commit replaces it.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    session: Session,
+    /// Files completed by an earlier run of the same session, with their blob SHAs.
+    completed: HashMap<String, String>,
+    /// Temporary files of earlier runs, of this session or another.
+    partials: Vec<String>,
+    resumed: bool,
+    file: Mutex<Option<std::fs::File>>,
+}
+
+impl Journal {
+    /// The session of the journal in `output`, if there is one.
+    pub fn read_session(output: &Path) -> Option<Session> {
+        let file = std::fs::File::open(output.join(JOURNAL_NAME)).ok()?;
+        let line = BufReader::new(file).lines().next()?.ok()?;
+        serde_json::from_str(&line).ok()
+    }
+
+    /// The journal for downloading `session` into `output`. A journal left there
+    /// by the same session is resumed; one of any other session is replaced
+    /// once the download starts.
+    pub fn open(output: &Path, session: Session) -> Self {
+        let path = output.join(JOURNAL_NAME);
+        let mut completed = HashMap::new();
+        let mut partials = Vec::new();
+        let mut resumed = false;
+        if let Ok(file) = std::fs::File::open(&path) {
+            let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+            let recorded: Option<Session> = lines.next().and_then(|line| serde_json::from_str(&line).ok());
+            resumed = recorded.as_ref() == Some(&session);
+            // A crash may have cut the last line short; it is skipped.
+            for entry in lines.filter_map(|line| serde_json::from_str::<Entry>(&line).ok()) {
+                match entry {
+                    Entry::Completed { path, sha } if resumed => {
+                        completed.insert(path, sha);
+                    }
+                    Entry::Completed { .. } => {}
+                    Entry::Partial { partial } if is_partial_name(&partial) => partials.push(partial),
+                    Entry::Partial { .. } => {}
+                }
+            }
+        }
+        Self {
+            path,
+            session,
+            completed,
+            partials,
+            resumed,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Whether this continues an earlier run, whose partial files can be reused.
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Whether an earlier run of this session finished writing `file`.
+    pub fn is_completed(&self, file: &GitHubFile) -> bool {
+        self.completed.get(&file.path) == Some(&file.sha)
+    }
+
+    fn output(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Creates the journal, or reopens it to append to when resuming. A journal
+    /// of another session is replaced, and its temporary files deleted.
+    pub fn start(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.output())?;
+        let file = if self.resumed {
+            let mut file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+            // Ends a line a crash may have cut short; blank lines are skipped.
+            writeln!(file)?;
+            file
+        } else {
+            self.remove_partials()?;
+            let mut file = std::fs::File::create(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&self.session)?)?;
+            file
+        };
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    fn append(&self, entry: &Entry) -> std::io::Result<()> {
+        match self.file.lock().unwrap().as_mut() {
+            Some(journal) => writeln!(journal, "{}", serde_json::to_string(entry)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Notes that a download is being written to `temp_path`, so that the file
+    /// can be cleaned up if it is never completed.
+    pub fn record_partial(&self, temp_path: &Path) -> std::io::Result<()> {
+        let partial = temp_path.strip_prefix(self.output()).unwrap_or(temp_path);
+        self.append(&Entry::Partial {
+            partial: partial.to_string_lossy().into_owned(),
+        })
+    }
+
+    /// Notes that `file` has been written completely.
+    pub fn record(&self, file: &GitHubFile) -> std::io::Result<()> {
+        self.append(&Entry::Completed {
+            path: file.path.clone(),
+            sha: file.sha.clone(),
+        })
+    }
+
+    fn remove_partials(&self) -> std::io::Result<()> {
+        for partial in &self.partials {
+            remove_if_present(&self.output().join(partial))?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the journal, and the temporary files of earlier runs, once there
+    /// is nothing left to resume.
+    pub fn remove(&self) -> std::io::Result<()> {
+        self.file.lock().unwrap().take();
+        self.remove_partials()?;
+        remove_if_present(&self.path)
+    }
+}
+
+/// Whether `partial` could have been recorded by `record_partial`: a relative
+/// path inside the output directory to a `.<name>.part` file. The journal is
+/// only a file in that directory, so other entries are never acted on.
+fn is_partial_name(partial: &str) -> bool {
+    let path = Path::new(partial);
+    let inside = path.components().all(|c| matches!(c, Component::Normal(_)));
+    let temporary = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+        name.len() > ".part".len() + 1 && name.starts_with('.') && name.ends_with(".part")
+    });
+    inside && temporary
+}
+
+fn remove_if_present(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resuming_a_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |path: &str, sha: &str| GitHubFile::fixture(path, sha, 0);
+        let session = Session {
+            repository: "github.com/user/repo".to_string(),
+            commit: "a".repeat(40),
+        };
+
+        let journal = Journal::open(dir.path(), session.clone());
+        assert!(!journal.is_resumed());
+        journal.start().unwrap();
+        journal.record(&file("a.txt", "1111")).unwrap();
+        journal.record(&file("b.txt", "2222")).unwrap();
+        drop(journal);
+        // A line cut short by a crash.
+        let mut contents = std::fs::read_to_string(dir.path().join(JOURNAL_NAME)).unwrap();
+        contents.push_str("{\"path\":\"c.t");
+        std::fs::write(dir.path().join(JOURNAL_NAME), contents).unwrap();
+
+        assert_eq!(Journal::read_session(dir.path()), Some(session.clone()));
+        let resumed = Journal::open(dir.path(), session.clone());
+        assert!(resumed.is_resumed());
+        assert!(resumed.is_completed(&file("a.txt", "1111")));
+        assert!(!resumed.is_completed(&file("b.txt", "3333")));
+        assert!(!resumed.is_completed(&file("c.txt", "4444")));
+        resumed.start().unwrap();
+        resumed.record(&file("c.txt", "4444")).unwrap();
+        assert!(Journal::open(dir.path(), session.clone()).is_completed(&file("c.txt", "4444")));
+
+        let partial = dir.path().join("docs/.d.txt.part");
+        std::fs::create_dir_all(partial.parent().unwrap()).unwrap();
+        std::fs::write(&partial, "half").unwrap();
+        resumed.record_partial(&partial).unwrap();
+        assert!(Journal::open(dir.path(), session.clone()).partials == ["docs/.d.txt.part"]);
+
+        // Entries that point outside the output directory, or at anything but
+        // a temporary file, are ignored rather than deleted.
+        let outside = dir.path().join("outside");
+        std::fs::write(&outside, "keep").unwrap();
+        for entry in ["docs/../outside", "../outside", "/etc/passwd", "README.md", ".part", "./.x.part"] {
+            assert!(!is_partial_name(entry), "{}", entry);
+            resumed.append(&Entry::Partial { partial: entry.to_string() }).unwrap();
+        }
+
+        // Starting over at another commit discards what the old session left.
+        let moved = Session {
+            commit: "b".repeat(40),
+            ..session
+        };
+        let fresh = Journal::open(dir.path(), moved.clone());
+        assert!(!fresh.is_resumed());
+        assert!(!fresh.is_completed(&file("a.txt", "1111")));
+        fresh.start().unwrap();
+        assert!(!partial.exists());
+        assert!(outside.exists());
+        assert_eq!(Journal::read_session(dir.path()), Some(moved));
+        fresh.remove().unwrap();
+        assert_eq!(Journal::read_session(dir.path()), None);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::github::{self, GitHubClient, GitHubFile, RepositoryInfo};
+use futures_util::stream::{self, StreamExt};
+use globset::{GlobBuilder, GlobMatcher};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// git-lfs never writes a pointer file larger than this.
+pub const MAX_POINTER_SIZE: u64 = 1024;
+const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+/// Objects per batch request, the limit GitHub's LFS server accepts.
+const BATCH_SIZE: usize = 100;
+
+/// The `oid`/`size` pair an LFS pointer file stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    pub oid: String,
+    pub size: u64,
+}
+
+/// Parses a Git LFS pointer file:
+///
+/// ```text
+/// version https://git-lfs.github.com/spec/v1
+/// oid sha256:4d7a2146...
+/// size 12345
+/// ```
+pub fn parse_pointer(content: &[u8]) -> Option<Pointer> {
+    if content.len() as u64 > MAX_POINTER_SIZE {
+        return None;
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let mut lines = text.lines();
+    if !lines.next()?.starts_with("version https://git-lfs.github.com/spec/") {
+        return None;
+    }
+    let (mut oid, mut size) = (None, None);
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("oid", value)) => oid = value.strip_prefix("sha256:").map(str::to_string),
+            Some(("size", value)) => size = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    let oid = oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+    Some(Pointer { oid, size: size? })
+}
+
+/// The `filter=lfs` rules of the `.gitattributes` files above and inside the
+/// downloaded directory.
+#[derive(Default)]
+pub struct LfsAttributes {
+    /// `(pattern, is_lfs)` in precedence order: the last matching rule wins.
+    rules: Vec<(GlobMatcher, bool)>,
+}
+
+impl LfsAttributes {
+    /// Adds the rules of the `.gitattributes` file in directory `dir`. Files must
+    /// be added from the root down, so deeper ones take precedence.
+    pub fn add(&mut self, dir: &str, content: &str) {
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            let is_lfs = fields.fold(None, |is_lfs, attribute| match attribute {
+                "filter=lfs" => Some(true),
+                "-filter" | "!filter" => Some(false),
+                a if a.starts_with("filter=") => Some(false),
+                _ => is_lfs,
+            });
+            if let (Some(is_lfs), Some(matcher)) = (is_lfs, attribute_glob(dir, pattern)) {
+                self.rules.push((matcher, is_lfs));
+            }
+        }
+    }
+
+    pub fn is_lfs(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .is_some_and(|(_, is_lfs)| *is_lfs)
+    }
+}
+
+/// Patterns without a slash match at any depth below their `.gitattributes`,
+/// the others are relative to its directory.
+fn attribute_glob(dir: &str, pattern: &str) -> Option<GlobMatcher> {
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let pattern = match (dir.is_empty(), anchored) {
+        (true, true) => pattern.to_string(),
+        (true, false) => format!("**/{}", pattern),
+        (false, true) => format!("{}/{}", dir, pattern),
+        (false, false) => format!("{}/**/{}", dir, pattern),
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|glob| glob.compile_matcher())
+}
+
+/// Where to download an LFS object from. The URL comes with its own
+/// authorization headers, so the GitHub token is never sent along.
+#[derive(Debug, Clone)]
+pub struct LfsObject {
+    /// SHA-256 of the content.
+    pub oid: String,
+    pub size: u64,
+    pub href: String,
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    operation: &'static str,
+    transfers: [&'static str; 1],
+    objects: Vec<ObjectSpec<'a>>,
+}
+
+#[derive(Serialize)]
+struct ObjectSpec<'a> {
+    oid: &'a str,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    oid: String,
+    size: u64,
+    actions: Option<Actions>,
+    error: Option<ObjectError>,
+}
+
+#[derive(Deserialize)]
+struct Actions {
+    download: Option<Action>,
+}
+
+#[derive(Deserialize)]
+struct Action {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ObjectError {
+    code: u16,
+    message: String,
+}
+
+/// Client for a repository's LFS batch API, authenticated with the same token
+/// as the REST API.
+#[derive(Clone)]
+pub struct LfsClient {
+    http: reqwest::Client,
+    endpoint: String,
+    token: Option<String>,
+}
+
+impl LfsClient {
+    pub fn new(repo_info: &RepositoryInfo, token: &Option<String>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent("gh-dir-rust-cli")
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            http,
+            endpoint: format!("{}.git/info/lfs/objects/batch", repo_info.web_url()),
+            token: token.clone(),
+        })
+    }
+
+    /// Asks the LFS server where to download each object. The results are in the
+    /// order of `pointers`; objects the server cannot serve come back as `Err`.
+    pub async fn resolve(&self, pointers: &[Pointer]) -> anyhow::Result<Vec<Result<LfsObject, String>>> {
+        let mut resolved: HashMap<String, Result<LfsObject, String>> = HashMap::new();
+        for chunk in pointers.chunks(BATCH_SIZE) {
+            let body = BatchRequest {
+                operation: "download",
+                transfers: ["basic"],
+                objects: chunk
+                    .iter()
+                    .map(|p| ObjectSpec { oid: &p.oid, size: p.size })
+                    .collect(),
+            };
+            let mut request = self
+                .http
+                .post(&self.endpoint)
+                .header(ACCEPT, LFS_MEDIA_TYPE)
+                .header(CONTENT_TYPE, LFS_MEDIA_TYPE)
+                .json(&body);
+            if let Some(token) = &self.token {
+                request = request.basic_auth("x-access-token", Some(token));
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                anyhow::bail!("Git LFS batch request failed with status {}: {}", status, body.trim());
+            }
+            let batch: BatchResponse = response.json().await?;
+            for object in batch.objects {
+                let result = match (object.error, object.actions.and_then(|a| a.download)) {
+                    (Some(error), _) => Err(format!("{} ({})", error.message, error.code)),
+                    (None, Some(action)) => Ok(LfsObject {
+                        oid: object.oid.clone(),
+                        size: object.size,
+                        href: action.href,
+                        headers: action.header.into_iter().collect(),
+                    }),
+                    (None, None) => Err("no download action".to_string()),
+                };
+                resolved.insert(object.oid, result);
+            }
+        }
+        Ok(pointers
+            .iter()
+            .map(|p| {
+                resolved
+                    .get(&p.oid)
+                    .cloned()
+                    .unwrap_or_else(|| Err("missing from the batch response".to_string()))
+            })
+            .collect())
+    }
+}
+
+/// Finds the LFS pointers among `files` — the small files `.gitattributes` routes
+/// through the LFS filter — and points them at their objects, so the preview shows
+/// real sizes and the download fetches real content. Returns warnings for objects
+/// that could not be resolved; those are downloaded as pointer files.
+pub async fn resolve_pointers(
+    client: &GitHubClient,
+    lfs: &LfsClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    files: &mut [GitHubFile],
+    concurrency: usize,
+) -> anyhow::Result<Vec<String>> {
+    let attributes = read_attributes(client, repo_info, directories, files).await?;
+    let candidates: Vec<usize> = (0..files.len())
+        .filter(|&i| {
+            let file = &files[i];
+            file.file_type == "file"
+                && file.lfs.is_none()
+                && file.size <= MAX_POINTER_SIZE
+                && attributes.is_lfs(&file.path)
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let contents: Vec<(usize, anyhow::Result<Vec<u8>>)> = stream::iter(candidates)
+        .map(|i| {
+            let file = &files[i];
+            async move { (i, github::fetch_file(client, file).await) }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    let mut pointers = Vec::new();
+    for (i, content) in contents {
+        let content = content.map_err(|e| anyhow::anyhow!("Could not read {}: {}", files[i].path, e))?;
+        if let Some(pointer) = parse_pointer(&content) {
+            pointers.push((i, pointer));
+        }
+    }
+    if pointers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let only_pointers: Vec<Pointer> = pointers.iter().map(|(_, p)| p.clone()).collect();
+    let mut warnings = Vec::new();
+    for ((i, _), result) in pointers.iter().zip(lfs.resolve(&only_pointers).await?) {
+        let file = &mut files[*i];
+        match result {
+            Ok(object) => {
+                file.size = object.size;
+                file.lfs = Some(object);
+            }
+            Err(e) => warnings.push(format!("{}: LFS object unavailable ({}), keeping the pointer file", file.path, e)),
+        }
+    }
+    Ok(warnings)
+}
+
+/// Reads the `.gitattributes` files that apply to the listing: those in the
+/// directories above the downloaded ones and those listed inside them.
+async fn read_attributes(
+    client: &GitHubClient,
+    repo_info: &RepositoryInfo,
+    directories: &[String],
+    files: &[GitHubFile],
+) -> anyhow::Result<LfsAttributes> {
+    let mut dirs = vec![String::new()];
+    for directory in directories {
+        let mut prefix = String::new();
+        for component in directory.split('/').filter(|c| !c.is_empty()) {
+            prefix = if prefix.is_empty() {
+                component.to_string()
+            } else {
+                format!("{}/{}", prefix, component)
+            };
+            if !dirs.contains(&prefix) {
+                dirs.push(prefix.clone());
+            }
+        }
+    }
+    for file in files.iter().filter(|f| f.name == ".gitattributes") {
+        let dir = file.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        if !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_string());
+        }
+    }
+    dirs.sort_by_key(|dir| dir.split('/').filter(|c| !c.is_empty()).count());
+
+    let mut attributes = LfsAttributes::default();
+    for dir in dirs {
+        let path = if dir.is_empty() {
+            ".gitattributes".to_string()
+        } else {
+            format!("{}/.gitattributes", dir)
+        };
+        if let Some(content) = github::read_file(client, repo_info, &path).await? {
+            attributes.add(&dir, &content);
+        }
+    }
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pointer() {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let pointer = format!("version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n", oid);
+        assert_eq!(
+            parse_pointer(pointer.as_bytes()),
+            Some(Pointer { oid: oid.to_string(), size: 12345 })
+        );
+
+        assert_eq!(parse_pointer(b"hello\n"), None);
+        assert_eq!(
+            parse_pointer(b"version https://git-lfs.github.com/spec/v1\noid sha256:abc\nsize 1\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut attributes = LfsAttributes::default();
+        attributes.add("", "# assets\n*.psd filter=lfs diff=lfs merge=lfs -text\n/models/*.bin filter=lfs\n");
+        attributes.add("docs", "*.psd -filter\nimg/** filter=lfs\n");
+
+        assert!(attributes.is_lfs("logo.psd"));
+        assert!(attributes.is_lfs("art/deep/logo.psd"));
+        assert!(attributes.is_lfs("models/weights.bin"));
+        assert!(!attributes.is_lfs("models/v2/weights.bin"));
+        assert!(!attributes.is_lfs("src/models/weights.bin"));
+        assert!(!attributes.is_lfs("docs/logo.psd"));
+        assert!(attributes.is_lfs("docs/img/a/b.png"));
+        assert!(!attributes.is_lfs("README.md"));
+    }
+}
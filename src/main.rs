@@ -4,8 +4,14 @@ use dialoguer::Confirm;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::time::Duration;
 
+mod cache;
 mod download;
+mod filter;
 mod github;
+mod journal;
+mod lfs;
+mod picker;
+mod repo_url;
 
 /// A Rust CLI to download GitHub directories
 #[derive(Parser, Debug)]
@@ -19,30 +25,113 @@ struct Cli {
 enum Commands {
     /// Clone a GitHub directory
     #[command(alias = "c")]
-    Clone(CloneArgs),
+    Clone(Box<CloneArgs>),
+    /// Delete the cached API responses
+    ClearCache,
 }
 
 #[derive(Args, Debug)]
 struct CloneArgs {
-    /// GitHub URL of the directory to download
+    /// GitHub URL of the directory or file to download (web, raw, API or SSH URL, or owner/repo/path@ref)
     url: String,
 
     /// Output directory or zip file
     #[arg(default_value = ".")]
     output: String,
 
+    /// Branch, tag or commit to download, overriding the one in the URL
+    #[arg(long = "ref", value_name = "REF")]
+    git_ref: Option<String>,
+
+    /// Directory or file inside the repository, or a URL of the same repository, to download along with
+    /// the one in the URL, or instead of the whole repository when the URL names no path (repeatable)
+    #[arg(long = "path", value_name = "PATH|URL")]
+    paths: Vec<String>,
+
     /// GitHub personal access token for private repos
     #[arg(short, long)]
     token: Option<String>,
 
+    /// Token for a specific host, as HOST=TOKEN (repeatable)
+    #[arg(long = "host-token", value_name = "HOST=TOKEN", value_parser = parse_host_token)]
+    host_tokens: Vec<(String, String)>,
+
+    /// REST API base URL, e.g. https://ghe.example.com/api/v3 (defaults to the one for the URL's host)
+    #[arg(long)]
+    api_url: Option<String>,
+
+    /// Only download files matching this glob (repeatable), e.g. '*.proto'
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files matching this glob (repeatable), e.g. 'tests/**'
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// File of gitignore-style patterns selecting what to download, like a sparse-checkout file
+    #[arg(long, value_name = "PATH")]
+    filter_file: Option<std::path::PathBuf>,
+
+    /// Skip files larger than this, e.g. 50MB
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Abort before downloading if the files add up to more than this, e.g. 1GB
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_total_size: Option<u64>,
+
+    /// Abort before downloading if there are more files than this
+    #[arg(long, value_name = "N")]
+    max_files: Option<usize>,
+
+    /// Only download files that changed since this branch, tag or commit
+    #[arg(long, value_name = "REF")]
+    changed_since: Option<String>,
+
+    /// Do not descend more than N directories below the one being downloaded; 1 downloads only its own files
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+
+    /// Only download the directory's own files, not its subdirectories (same as --max-depth 1)
+    #[arg(long, conflicts_with = "max_depth")]
+    no_recursive: bool,
+
     /// Download as zip file instead of extracting files
     #[arg(short, long)]
     zip: bool,
 
+    /// Continue the unfinished download in the output directory at the commit it started from
+    #[arg(long, conflicts_with_all = ["git_ref", "zip"])]
+    resume: bool,
+
+    /// Download the contents of git submodules instead of skipping them
+    #[arg(long)]
+    recurse_submodules: bool,
+
+    /// Do not check downloaded files against their git blob SHA or Git LFS object SHA-256
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Download Git LFS pointer files as they are instead of the objects they point at
+    #[arg(long)]
+    no_lfs: bool,
+
     /// Number of concurrent downloads
     #[arg(short, long, default_value_t = 10)]
     concurrency: u8,
 
+    /// Do not use or update the on-disk cache of API responses
+    #[arg(long)]
+    no_cache: bool,
+
+    /// When the API rate limit is hit, wait until it resets instead of failing
+    #[arg(long)]
+    wait_on_rate_limit: bool,
+
+    /// Number of concurrent directory listing requests
+    #[arg(long, default_value_t = 4)]
+    list_concurrency: usize,
+
     /// Enable debug output
     #[arg(short, long)]
     debug: bool,
@@ -58,6 +147,10 @@ struct CloneArgs {
     /// Skip confirmation prompt
     #[arg(short = 'y', long)]
     yes: bool,
+
+    /// Pick the files to download from a tree instead of confirming the whole listing
+    #[arg(short, long, conflicts_with = "yes")]
+    interactive: bool,
 }
 
 #[tokio::main]
@@ -71,6 +164,12 @@ async fn main() -> anyhow::Result<()> {
                 std::process::exit(1);
             }
         }
+        Commands::ClearCache => {
+            if let Err(e) = clear_cache() {
+                eprintln!("{} {}", style("Error:").red().bold(), e);
+                std::process::exit(1);
+            }
+        }
     }
 
     Ok(())
@@ -89,43 +188,508 @@ async fn run_clone(args: &CloneArgs) -> anyhow::Result<()> {
     spinner.set_message("Analyzing repository...");
     spinner.enable_steady_tick(Duration::from_millis(100));
 
-    let repo_info = github::get_repository_info(&args.url).await?;
+    let mut repo_info = github::get_repository_info(&args.url, &enterprise_hosts(args)).await?;
+    if let Some(api_url) = &args.api_url {
+        repo_info.api_base = api_url.trim_end_matches('/').to_string();
+    }
+    let token = token_for_host(args, &repo_info.host);
+    let client = github::GitHubClient::new(&token, args.wait_on_rate_limit, response_cache(args), spinner.clone())?;
     spinner.set_message(format!(
         "Repository: {}/{}",
         style(repo_info.user.clone()).cyan().bold(),
         style(repo_info.repository.clone()).cyan().bold()
     ));
 
-    let (files, total_size) = github::get_all_files(&repo_info, &args.token).await?;
-    spinner.finish_with_message(format!("Found {} files.", style(files.len()).cyan().bold()));
+    // --resume stays at the commit the unfinished download started from, even if
+    // the branch has moved since.
+    let repository = format!("{}/{}/{}", repo_info.host, repo_info.user, repo_info.repository);
+    let resumed_commit = if args.resume {
+        let Some(session) = journal::Journal::read_session(std::path::Path::new(&args.output)) else {
+            anyhow::bail!("There is no unfinished download in {} to resume.", args.output);
+        };
+        if !session.repository.eq_ignore_ascii_case(&repository) {
+            anyhow::bail!(
+                "The unfinished download in {} is of {}, not {}.",
+                args.output,
+                session.repository,
+                repository
+            );
+        }
+        Some(session.commit)
+    } else {
+        None
+    };
+    github::resolve_reference(&client, &mut repo_info, resumed_commit.as_deref().or(args.git_ref.as_deref())).await?;
+    let resolved_ref = repo_info
+        .resolved_ref
+        .clone()
+        .expect("resolve_reference always pins a commit");
+    let directories = source_directories(args, &client, &repo_info).await?;
+    if let Some(fork) = &resolved_ref.fork {
+        spinner.suspend(|| {
+            eprintln!(
+                "{} the head of this pull request lives in {}; downloading its head commit through {}/{}.",
+                style("Warning:").yellow().bold(),
+                style(fork).cyan(),
+                repo_info.user,
+                repo_info.repository
+            )
+        });
+    }
+    spinner.set_message(format!(
+        "Listing files at {} {} ({})...",
+        resolved_ref.kind,
+        style(&resolved_ref.name).cyan().bold(),
+        style(resolved_ref.short_sha()).dim()
+    ));
+
+    let mut path_filter = filter::PathFilter::new(&directories, &args.include, &args.exclude)?;
+    if let Some(filter_file) = &args.filter_file {
+        let rules = std::fs::read_to_string(filter_file)
+            .map_err(|e| anyhow::anyhow!("Could not read {}: {}", filter_file.display(), e))?;
+        path_filter
+            .add_rules(&rules)
+            .map_err(|e| anyhow::anyhow!("{}: {}", filter_file.display(), e))?;
+    }
+    let list_options = github::ListOptions {
+        concurrency: args.list_concurrency,
+        filter: path_filter.clone(),
+        max_depth: if args.no_recursive {
+            Some(1)
+        } else {
+            args.max_depth.map(|depth| depth as usize)
+        },
+    };
+    let listing = github::get_all_files(&client, &repo_info, &directories, &list_options).await?;
+    let mut files = listing.files;
+    let mut warnings = listing.warnings;
+    if args.recurse_submodules {
+        spinner.set_message("Listing submodules...");
+        files = github::expand_submodules(&client, &repo_info, &directories, files, &list_options).await?;
+    }
+    let (skipped_submodules, rest): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .partition(|f| f.is_submodule() && !path_filter.excludes_dir(&f.path));
+    // Directories left in the listing either hold nothing the filter selects or
+    // are past --max-depth.
+    let (not_descended, rest): (Vec<_>, Vec<_>) = rest
+        .into_iter()
+        .partition(|f| f.is_dir() && !path_filter.excludes_dir(&f.path));
+    let (kept, filtered_out): (Vec<_>, Vec<_>) = rest
+        .into_iter()
+        .partition(|f| !f.is_dir() && path_filter.matches(&f.path));
+    files = kept;
+    let comparison = match &args.changed_since {
+        Some(reference) => {
+            spinner.set_message(format!("Comparing with {}...", style(reference).cyan().bold()));
+            let mut base_info = repo_info.clone();
+            github::resolve_reference(&client, &mut base_info, Some(reference)).await?;
+            let base = base_info.resolved_ref.expect("resolve_reference always pins a commit");
+            let mut changes =
+                github::changes_since(&client, &repo_info, &directories, &base.sha, &files, &list_options).await?;
+            warnings.append(&mut changes.warnings);
+            let (changed, unchanged): (Vec<_>, Vec<_>) =
+                files.into_iter().partition(|f| is_changed(&changes.changed, &f.path));
+            files = changed;
+            Some((base, changes, unchanged.len()))
+        }
+        None => None,
+    };
+    let lfs_client = if args.no_lfs {
+        None
+    } else {
+        Some(lfs::LfsClient::new(&repo_info, &token)?)
+    };
+    if let Some(lfs_client) = &lfs_client {
+        spinner.set_message("Checking for Git LFS files...");
+        warnings.extend(
+            lfs::resolve_pointers(&client, lfs_client, &repo_info, &directories, &mut files, args.list_concurrency).await?,
+        );
+    }
+    let oversized = match args.max_file_size {
+        Some(max_file_size) => {
+            let (oversized, rest): (Vec<_>, Vec<_>) =
+                files.into_iter().partition(|f| f.size > max_file_size);
+            files = rest;
+            oversized
+        }
+        None => Vec::new(),
+    };
+    // A ZIP archive is written from scratch, so only a directory can already
+    // hold some of the files, or an unfinished download to continue.
+    let journal = (!args.zip).then(|| {
+        journal::Journal::open(
+            std::path::Path::new(&args.output),
+            journal::Session {
+                repository,
+                commit: resolved_ref.sha.clone(),
+            },
+        )
+    });
+    let up_to_date = if args.zip {
+        Vec::new()
+    } else {
+        spinner.set_message("Checking existing files...");
+        let (missing, up_to_date) =
+            download::split_up_to_date(files, &args.output, args.list_concurrency, journal.as_ref()).await;
+        files = missing;
+        up_to_date
+    };
+    let total_size: u64 = files.iter().map(|f| f.size).sum();
+    spinner.finish_with_message(format!(
+        "Found {} files.",
+        style(files.len() + up_to_date.len()).cyan().bold()
+    ));
+    for warning in &warnings {
+        term.write_line(&format!("{} {}", style("Warning:").yellow().bold(), warning))?;
+    }
+    if !skipped_submodules.is_empty() {
+        term.write_line(&format!(
+            "{} skipping {} submodule(s), use --recurse-submodules to download them:",
+            style("Note:").cyan().bold(),
+            skipped_submodules.len()
+        ))?;
+        for submodule in &skipped_submodules {
+            term.write_line(&format!("  {} @ {}", submodule.path, &submodule.sha[..submodule.sha.len().min(7)]))?;
+        }
+    }
+    if let (Some(max_file_size), false) = (args.max_file_size, oversized.is_empty()) {
+        term.write_line(&format!(
+            "{} skipping {} file(s) larger than {} (--max-file-size):",
+            style("Note:").cyan().bold(),
+            oversized.len(),
+            format_size(max_file_size)
+        ))?;
+        for file in &oversized {
+            term.write_line(&format!("  {} ({})", file.path, format_size(file.size)))?;
+        }
+    }
+    if let Some((base, changes, _)) = &comparison {
+        if !changes.removed.is_empty() {
+            term.write_line(&format!(
+                "{} {} file(s) removed since {} {}:",
+                style("Note:").cyan().bold(),
+                changes.removed.len(),
+                base.kind,
+                base.name
+            ))?;
+            for path in &changes.removed {
+                term.write_line(&format!("  {}", path))?;
+            }
+        }
+        if !changes.renamed.is_empty() {
+            term.write_line(&format!(
+                "{} {} file(s) renamed since {} {}:",
+                style("Note:").cyan().bold(),
+                changes.renamed.len(),
+                base.kind,
+                base.name
+            ))?;
+            for (old, new) in &changes.renamed {
+                term.write_line(&format!("  {} -> {}", old, new))?;
+            }
+        }
+        if files.is_empty() && up_to_date.is_empty() {
+            term.write_line(&format!(
+                "No files changed since {} {}; nothing to download.",
+                base.kind, base.name
+            ))?;
+            return Ok(());
+        }
+    }
+    if files.is_empty() && !up_to_date.is_empty() {
+        term.write_line(&format!(
+            "All {} files in {} are up to date; nothing to download.",
+            up_to_date.len(),
+            args.output
+        ))?;
+        if let Some(journal) = &journal {
+            journal.remove()?;
+        }
+        return Ok(());
+    }
+    if let Some(max_files) = args.max_files.filter(|max| files.len() > *max) {
+        anyhow::bail!(
+            "{} files to download, more than --max-files {}; nothing was downloaded.",
+            files.len(),
+            max_files
+        );
+    }
+    if let Some(max_total_size) = args.max_total_size.filter(|max| total_size > *max) {
+        anyhow::bail!(
+            "The files add up to {}, more than --max-total-size {}; nothing was downloaded.",
+            format_size(total_size),
+            format_size(max_total_size)
+        );
+    }
+
+    let mut summary = vec![
+        (
+            "Ref:".to_string(),
+            format!(
+                "{} {} @ {}",
+                resolved_ref.kind,
+                resolved_ref.name,
+                resolved_ref.short_sha()
+            ),
+        ),
+        ("Total size:".to_string(), format_size(total_size)),
+    ];
+    if !path_filter.is_empty() {
+        summary.push(("Filtered out:".to_string(), describe_filtered(&filtered_out)));
+    }
+    if let Some((base, _, unchanged)) = &comparison {
+        summary.push((
+            "Changed since:".to_string(),
+            format!("{} {} @ {} ({} unchanged skipped)", base.kind, base.name, base.short_sha(), unchanged),
+        ));
+    }
+    if journal.as_ref().is_some_and(journal::Journal::is_resumed) {
+        summary.push((
+            "Resuming:".to_string(),
+            format!("the unfinished download in {}", args.output),
+        ));
+    }
+    if !up_to_date.is_empty() {
+        summary.push((
+            "Up to date:".to_string(),
+            format!("{} file(s) already in {}, skipped", up_to_date.len(), args.output),
+        ));
+    }
+    if let Some(rate_limit) = client.rate_limit() {
+        summary.push(("API quota:".to_string(), rate_limit.to_string()));
+    }
 
     // Preview files - use the original approach but with better box drawing
     if args.plain {
-        show_plain_preview(&files, total_size, args.preview_limit)?;
+        show_plain_preview(&files, &not_descended, &summary, args.preview_limit)?;
     } else {
-        show_boxed_preview(&files, total_size, args.preview_limit)?;
+        show_boxed_preview(&files, &not_descended, &summary, args.preview_limit)?;
     }
 
-    // Ask for confirmation unless --yes flag is used
-    if !args.yes {
-        if !Confirm::new()
+    // Let the user pick files with --interactive, otherwise ask for confirmation
+    // unless --yes flag is used
+    if args.interactive {
+        match picker::pick_files(files, &directories)? {
+            Some(selected) if !selected.is_empty() => files = selected,
+            _ => {
+                term.write_line(&format!("{}", style("Nothing selected, aborting.").red().bold()))?;
+                return Ok(());
+            }
+        }
+    } else if !args.yes
+        && !Confirm::new()
             .with_prompt(format!("{}", style("Continue?").green().bold()))
             .default(true)
-            .interact()? 
+            .interact()?
+    {
+        term.write_line(&format!("{}", style("Aborting.").red().bold()))?;
+        return Ok(());
+    }
+
+    let options = download::DownloadOptions {
+        concurrency: args.concurrency as usize,
+        token,
+        lfs: lfs_client,
+        verify: !args.no_verify,
+        journal: journal.as_ref(),
+    };
+    let report = download::download_files(files, &args.output, args.zip, &options).await?;
+    if report.failed.is_empty() && report.interrupted.is_empty() {
+        if let Some(journal) = &journal {
+            journal.remove()?;
+        }
+        term.write_line(&format!("\n{}", style("Done!").green().bold()))?;
+        return Ok(());
+    }
+
+    let total = report.completed.len() + report.failed.len() + report.interrupted.len();
+    term.write_line(&format!(
+        "\n{} {} of {} files.",
+        style("Downloaded").bold(),
+        style(report.completed.len()).cyan().bold(),
+        total
+    ))?;
+    if !report.failed.is_empty() {
+        term.write_line(&format!("{} {} file(s):", style("Failed:").red().bold(), report.failed.len()))?;
+        for (path, error) in &report.failed {
+            term.write_line(&format!("  {}: {}", path, error))?;
+        }
+    }
+    if !report.interrupted.is_empty() {
+        term.write_line(&format!(
+            "{} {} file(s) not downloaded:",
+            style("Interrupted:").yellow().bold(),
+            report.interrupted.len()
+        ))?;
+        for path in &report.interrupted {
+            term.write_line(&format!("  {}", path))?;
+        }
+    }
+    if journal.is_some() {
+        term.write_line(&format!(
+            "{} run the same command again to continue where this one stopped, or add --resume to stay at commit {}.",
+            style("Note:").cyan().bold(),
+            resolved_ref.short_sha()
+        ))?;
+    }
+    if report.interrupted.is_empty() {
+        anyhow::bail!("{} file(s) could not be downloaded.", report.failed.len());
+    }
+    anyhow::bail!("Interrupted; {} file(s) were not downloaded.", report.failed.len() + report.interrupted.len())
+}
+
+/// The paths to download: the URL's, together with those given with `--path`.
+/// A URL of the repository root, or of a pull request, commit or release page,
+/// names no path of its own, so there only the `--path`s are downloaded. A
+/// `--path` URL has to point into the same repository, at the same commit.
+async fn source_directories(
+    args: &CloneArgs,
+    client: &github::GitHubClient,
+    repo_info: &github::RepositoryInfo,
+) -> anyhow::Result<Vec<String>> {
+    if args.paths.is_empty() {
+        return Ok(vec![repo_info.directory.clone()]);
+    }
+    let resolved_ref = repo_info.resolved_ref.as_ref().expect("resolve_reference always pins a commit");
+    let mut directories = Vec::new();
+    if !repo_info.directory.is_empty() {
+        directories.push(repo_info.directory.clone());
+    }
+    for path in &args.paths {
+        if !path.contains("://") && !path.starts_with("git@") {
+            directories.push(path.trim_matches('/').to_string());
+            continue;
+        }
+        let mut source = github::get_repository_info(path, &enterprise_hosts(args)).await?;
+        if !source.host.eq_ignore_ascii_case(&repo_info.host)
+            || !source.user.eq_ignore_ascii_case(&repo_info.user)
+            || !source.repository.eq_ignore_ascii_case(&repo_info.repository)
         {
-            term.write_line(&format!("{}", style("Aborting.").red().bold()))?;
-            return Ok(());
+            anyhow::bail!(
+                "{} is not in {}/{}; all paths have to come from the same repository.",
+                path,
+                repo_info.user,
+                repo_info.repository
+            );
+        }
+        // Without a ref of its own, the URL is taken at the main URL's commit.
+        if source.git_reference.is_some() || source.pull_request.is_some() {
+            source.api_base = repo_info.api_base.clone();
+            github::resolve_reference(client, &mut source, args.git_ref.as_deref()).await?;
+            let source_ref = source.resolved_ref.as_ref().expect("resolve_reference always pins a commit");
+            if source_ref.sha != resolved_ref.sha {
+                anyhow::bail!(
+                    "{} is at {} {} ({}), but the URL is at {} {} ({}); all paths have to be at the same ref.",
+                    path,
+                    source_ref.kind,
+                    source_ref.name,
+                    source_ref.short_sha(),
+                    resolved_ref.kind,
+                    resolved_ref.name,
+                    resolved_ref.short_sha()
+                );
+            }
         }
+        directories.push(source.directory);
     }
+    Ok(directories)
+}
 
-    download::download_files(files, &args.output, args.concurrency, args.zip).await?;
+/// Whether the file at `path` changed, or is inside a changed submodule.
+fn is_changed(changed: &std::collections::HashSet<String>, path: &str) -> bool {
+    let mut candidate = path;
+    loop {
+        if changed.contains(candidate) {
+            return true;
+        }
+        match candidate.rsplit_once('/') {
+            Some((parent, _)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
 
-    term.write_line(&format!("\n{}", style("Done!").green().bold()))?;
+/// "12 files, 2 directories not listed"
+fn describe_filtered(filtered_out: &[github::GitHubFile]) -> String {
+    let dirs = filtered_out.iter().filter(|f| f.is_dir()).count();
+    let files = filtered_out.len() - dirs;
+    let mut description = format!("{} file{}", files, if files == 1 { "" } else { "s" });
+    if dirs > 0 {
+        description.push_str(&format!(
+            ", {} director{} not listed",
+            dirs,
+            if dirs == 1 { "y" } else { "ies" }
+        ));
+    }
+    description
+}
 
+fn response_cache(args: &CloneArgs) -> Option<cache::ResponseCache> {
+    if args.no_cache {
+        return None;
+    }
+    cache::ResponseCache::default_dir().map(cache::ResponseCache::new)
+}
+
+fn clear_cache() -> anyhow::Result<()> {
+    let Some(dir) = cache::ResponseCache::default_dir() else {
+        anyhow::bail!("Could not determine the cache directory");
+    };
+    let cache = cache::ResponseCache::new(dir);
+    let removed = cache.clear()?;
+    println!(
+        "Removed {} cached response(s) from {}",
+        style(removed).cyan().bold(),
+        cache.dir().display()
+    );
     Ok(())
 }
 
-fn show_plain_preview(files: &[github::GitHubFile], total_size: u64, limit: usize) -> anyhow::Result<()> {
+fn parse_host_token(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((host, token)) if !host.is_empty() && !token.is_empty() => {
+            Ok((repo_url::normalize_host(host), token.to_string()))
+        }
+        _ => Err("expected HOST=TOKEN".to_string()),
+    }
+}
+
+/// Hosts besides github.com that URLs may point at: every host with a configured
+/// token, the host of `--api-url`, and `GH_HOST` (as used by the GitHub CLI).
+fn enterprise_hosts(args: &CloneArgs) -> Vec<String> {
+    let mut hosts: Vec<String> = args.host_tokens.iter().map(|(host, _)| host.clone()).collect();
+    hosts.extend(args.api_url.as_deref().map(repo_url::normalize_host));
+    hosts.extend(std::env::var("GH_HOST").ok().map(|h| repo_url::normalize_host(&h)));
+    hosts
+}
+
+/// `--token` wins, then a matching `--host-token`, then the environment:
+/// `GITHUB_TOKEN`/`GH_TOKEN` for github.com and `GH_ENTERPRISE_TOKEN`/
+/// `GITHUB_ENTERPRISE_TOKEN` for enterprise hosts.
+fn token_for_host(args: &CloneArgs, host: &str) -> Option<String> {
+    if let Some(token) = &args.token {
+        return Some(token.clone());
+    }
+    if let Some((_, token)) = args.host_tokens.iter().find(|(h, _)| h == host) {
+        return Some(token.clone());
+    }
+    let env_vars: &[&str] = if host == "github.com" {
+        &["GITHUB_TOKEN", "GH_TOKEN"]
+    } else {
+        &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"]
+    };
+    env_vars
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|t| !t.is_empty()))
+}
+
+fn show_plain_preview(
+    files: &[github::GitHubFile],
+    not_descended: &[github::GitHubFile],
+    summary: &[(String, String)],
+    limit: usize,
+) -> anyhow::Result<()> {
     let term = Term::stdout();
     
     term.write_line(&format!("\n{}", style("Files to be downloaded:").bold()))?;
@@ -141,15 +705,26 @@ fn show_plain_preview(files: &[github::GitHubFile], total_size: u64, limit: usiz
             style("...").dim(), 
             style(files.len() - limit).dim()))?;
     }
+    for dir in not_descended {
+        term.write_line(&format!("  {}/ {}", style(&dir.path).cyan(), style("(not descended into)").dim()))?;
+    }
     
-    term.write_line(&format!("\n{} {}", 
-        style("Total size:").bold(), 
-        style(format_size(total_size)).cyan().bold()))?;
+    term.write_line("")?;
+    for (label, value) in summary {
+        term.write_line(&format!("{} {}", 
+            style(label).bold(), 
+            style(value).cyan().bold()))?;
+    }
     
     Ok(())
 }
 
-fn show_boxed_preview(files: &[github::GitHubFile], total_size: u64, limit: usize) -> anyhow::Result<()> {
+fn show_boxed_preview(
+    files: &[github::GitHubFile],
+    not_descended: &[github::GitHubFile],
+    summary: &[(String, String)],
+    limit: usize,
+) -> anyhow::Result<()> {
     let term = Term::stdout();
     
     // Prepare file list content
@@ -163,27 +738,27 @@ fn show_boxed_preview(files: &[github::GitHubFile], total_size: u64, limit: usiz
     if files.len() > limit {
         file_list_content.push_str(&format!("... ({} more files not shown)\n", files.len() - limit));
     }
+    for dir in not_descended {
+        file_list_content.push_str(&format!("{}/ (not descended into)\n", dir.path));
+    }
 
     let boxed_content = draw_box(
-        &file_list_content.trim_end(),
+        file_list_content.trim_end(),
         "Files to be downloaded",
-        format_size(total_size),
+        summary,
     );
     term.write_line(&boxed_content)?;
     
     Ok(())
 }
 
-fn draw_box(content: &str, title: &str, total_size_str: String) -> String {
+fn draw_box(content: &str, title: &str, summary: &[(String, String)]) -> String {
     use console::{style, measure_text_width};
     
     let lines: Vec<&str> = content.lines().collect();
     
     // Calculate the required inner width (content width) using unstyled text
     let title_len = measure_text_width(title);
-    let total_size_label_text = "Total size:";
-    let total_size_label_len = measure_text_width(total_size_label_text);
-    let total_size_str_len = measure_text_width(&total_size_str);
     
     // Find the maximum content width needed
     let max_line_width = lines
@@ -192,12 +767,16 @@ fn draw_box(content: &str, title: &str, total_size_str: String) -> String {
         .max()
         .unwrap_or(0);
     
-    let total_size_line_width = total_size_label_len + 1 + total_size_str_len; // +1 for space
+    let summary_line_width = summary
+        .iter()
+        .map(|(label, value)| measure_text_width(label) + 1 + measure_text_width(value)) // +1 for space
+        .max()
+        .unwrap_or(0);
     
     // The inner width should accommodate all content
     let inner_width = max_line_width
         .max(title_len)
-        .max(total_size_line_width);
+        .max(summary_line_width);
     
     // Add small buffer for safety and ensure minimum width
     let inner_width = (inner_width + 2).max(20); // Minimum width of 20
@@ -238,25 +817,27 @@ fn draw_box(content: &str, title: &str, total_size_str: String) -> String {
         ));
     }
     
-    // Separator above total size: ├────────┤
+    // Separator above the summary: ├────────┤
     boxed_string.push_str(&format!(
         "{}\n",
         style(format!("├{}┤", horizontal_line)).blue().bold()
     ));
     
-    // Total size line: │ Total size: 26.33 KB │
-    let total_size_line_content = format!(
-        "{} {:>width$}",
-        total_size_label_text,
-        total_size_str,
-        width = inner_width - total_size_label_len - 1 // -1 for space between label and value
-    );
-    boxed_string.push_str(&format!(
-        "{} {} {}\n",
-        style("│").blue().bold(),
-        style(total_size_line_content).bold().cyan(),
-        style("│").blue().bold()
-    ));
+    // Summary lines: │ Total size: 26.33 KB │
+    for (label, value) in summary {
+        let summary_line_content = format!(
+            "{} {:>width$}",
+            label,
+            value,
+            width = inner_width - measure_text_width(label) - 1 // -1 for space between label and value
+        );
+        boxed_string.push_str(&format!(
+            "{} {} {}\n",
+            style("│").blue().bold(),
+            style(summary_line_content).bold().cyan(),
+            style("│").blue().bold()
+        ));
+    }
     
     // Bottom border: ╰────────╯
     boxed_string.push_str(&format!(
@@ -266,25 +847,44 @@ fn draw_box(content: &str, title: &str, total_size_str: String) -> String {
     
     boxed_string
 }
+/// The units `format_size` prints and `parse_size` accepts, largest first.
+const SIZE_UNITS: [(&str, u64); 4] = [
+    ("TB", 1 << 40),
+    ("GB", 1 << 30),
+    ("MB", 1 << 20),
+    ("KB", 1 << 10),
+];
+
 fn format_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = 1024 * KB;
-    const GB: u64 = 1024 * MB;
-    const TB: u64 = 1024 * GB;
-
-    if bytes >= TB {
-        format!("{:.2} TB", bytes as f64 / TB as f64)
-    } else if bytes >= GB {
-        format!("{:.2} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} B", bytes)
+    match SIZE_UNITS.iter().find(|(_, unit)| bytes >= *unit) {
+        Some((name, unit)) => format!("{:.2} {}", bytes as f64 / *unit as f64, name),
+        None => format!("{} B", bytes),
     }
 }
 
+/// Parses sizes such as `512`, `512 B`, `50MB` or `1.5 gb`, in the units
+/// `format_size` prints (1 KB = 1024 bytes).
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}', expected e.g. 500KB or 1.5GB", value))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let multiplier = match unit.as_str() {
+        "" | "B" => 1,
+        _ => SIZE_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, multiplier)| *multiplier)
+            .ok_or_else(|| format!("unknown unit '{}', expected B, KB, MB, GB or TB", unit))?,
+    };
+    Ok((number * multiplier as f64).round() as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -300,10 +900,34 @@ mod tests {
         assert_eq!(format_size(1099511627776), "1.00 TB");
     }
 
+    #[test]
+    fn test_is_changed() {
+        let changed = ["src/main.rs".to_string(), "vendor/lib".to_string()].into_iter().collect();
+        assert!(is_changed(&changed, "src/main.rs"));
+        assert!(is_changed(&changed, "vendor/lib/src/lib.rs"));
+        assert!(!is_changed(&changed, "src/lib.rs"));
+        assert!(!is_changed(&changed, "vendor/library.rs"));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512 B"), Ok(512));
+        assert_eq!(parse_size("50MB"), Ok(50 * 1048576));
+        assert_eq!(parse_size("1.5 gb"), Ok(1610612736));
+        assert_eq!(parse_size("1TB"), Ok(1099511627776));
+        for bytes in [0, 512, 1536, 1048576, 5 * 1073741824] {
+            assert_eq!(parse_size(&format_size(bytes)), Ok(bytes));
+        }
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10 XB").is_err());
+    }
+
     #[test]
     fn test_box_drawing_alignment() {
         let content = "file1.txt\nfile2.txt\nvery_long_filename_here.txt";
-        let result = draw_box(content, "Test Files", "1.23 KB".to_string());
+        let summary = vec![("Total size:".to_string(), "1.23 KB".to_string())];
+        let result = draw_box(content, "Test Files", &summary);
         
         println!("{}", result);
         
@@ -0,0 +1,306 @@
+use crate::format_size;
+use crate::github::GitHubFile;
+use console::{style, Key, Term};
+
+/// A directory or file in the picker's tree. Node 0 is the downloaded
+/// directory itself and is not shown.
+#[derive(Debug)]
+struct Node {
+    name: String,
+    depth: usize,
+    children: Vec<usize>,
+    /// Index into the listing for files, `None` for directories.
+    file: Option<usize>,
+    expanded: bool,
+    size: u64,
+}
+
+/// Whether everything, nothing or only part of a node is selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    All,
+    None,
+    Partial,
+}
+
+/// The state behind `--interactive`: the listing as a collapsible tree in
+/// which whole directories or single files can be selected.
+#[derive(Debug)]
+struct FileTree {
+    nodes: Vec<Node>,
+    selected: Vec<bool>,
+    sizes: Vec<u64>,
+}
+
+impl FileTree {
+    /// Builds the tree from `files` (in listing order), with paths taken
+    /// relative to `base`. Everything starts selected, with directories collapsed.
+    fn new(files: &[GitHubFile], base: &str) -> Self {
+        let base = base.trim_matches('/');
+        let mut nodes = vec![Node {
+            name: String::new(),
+            depth: 0,
+            children: Vec::new(),
+            file: None,
+            expanded: true,
+            size: 0,
+        }];
+        for (index, file) in files.iter().enumerate() {
+            let relative = match file.path.strip_prefix(base) {
+                Some(rest) if !base.is_empty() && rest.starts_with('/') => &rest[1..],
+                _ if base.is_empty() => file.path.as_str(),
+                _ => file.path.rsplit('/').next().unwrap_or(&file.path),
+            };
+            let components: Vec<&str> = relative.split('/').collect();
+            let mut parent = 0;
+            for (depth, component) in components.iter().enumerate() {
+                nodes[parent].size += file.size;
+                let is_file = depth == components.len() - 1;
+                let existing = nodes[parent]
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|&child| nodes[child].file.is_none() && !is_file && nodes[child].name == *component);
+                parent = match existing {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(Node {
+                            name: component.to_string(),
+                            depth,
+                            children: Vec::new(),
+                            file: is_file.then_some(index),
+                            expanded: false,
+                            size: 0,
+                        });
+                        let child = nodes.len() - 1;
+                        nodes[parent].children.push(child);
+                        child
+                    }
+                };
+            }
+            nodes[parent].size = file.size;
+        }
+        Self {
+            nodes,
+            selected: vec![true; files.len()],
+            sizes: files.iter().map(|f| f.size).collect(),
+        }
+    }
+
+    /// The nodes currently shown, in display order.
+    fn rows(&self) -> Vec<usize> {
+        let mut rows = Vec::new();
+        let mut stack: Vec<usize> = self.nodes[0].children.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            rows.push(node);
+            if self.nodes[node].expanded {
+                stack.extend(self.nodes[node].children.iter().rev());
+            }
+        }
+        rows
+    }
+
+    /// The listing indices of every file at or below `node`.
+    fn files_under(&self, node: usize) -> Vec<usize> {
+        let mut files = Vec::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            files.extend(self.nodes[node].file);
+            stack.extend(&self.nodes[node].children);
+        }
+        files
+    }
+
+    fn selection(&self, node: usize) -> Selection {
+        let files = self.files_under(node);
+        let selected = files.iter().filter(|&&i| self.selected[i]).count();
+        match selected {
+            0 => Selection::None,
+            n if n == files.len() => Selection::All,
+            _ => Selection::Partial,
+        }
+    }
+
+    /// Selects everything at or below `node`, or deselects it if it is all selected.
+    fn toggle(&mut self, node: usize) {
+        let select = self.selection(node) != Selection::All;
+        for i in self.files_under(node) {
+            self.selected[i] = select;
+        }
+    }
+
+    fn selected_count(&self) -> usize {
+        self.selected.iter().filter(|&&s| s).count()
+    }
+
+    fn selected_size(&self) -> u64 {
+        self.selected
+            .iter()
+            .zip(&self.sizes)
+            .filter(|(&selected, _)| selected)
+            .map(|(_, size)| size)
+            .sum()
+    }
+
+    fn parent(&self, node: usize) -> Option<usize> {
+        (1..self.nodes.len()).find(|&parent| self.nodes[parent].children.contains(&node))
+    }
+}
+
+/// The deepest directory all of `directories` are in.
+fn common_directory(directories: &[String]) -> String {
+    let mut common: Vec<&str> = match directories.first() {
+        Some(first) => first.split('/').filter(|c| !c.is_empty()).collect(),
+        None => return String::new(),
+    };
+    for directory in &directories[1..] {
+        let components: Vec<&str> = directory.split('/').filter(|c| !c.is_empty()).collect();
+        let shared = common.iter().zip(&components).take_while(|(a, b)| a == b).count();
+        common.truncate(shared);
+    }
+    common.join("/")
+}
+
+/// Lets the user pick files from the listing of `directories` in the terminal
+/// and returns the chosen ones, or `None` if they cancelled.
+pub fn pick_files(files: Vec<GitHubFile>, directories: &[String]) -> anyhow::Result<Option<Vec<GitHubFile>>> {
+    let term = Term::stdout();
+    if !term.is_term() {
+        anyhow::bail!("--interactive needs a terminal; use --include/--exclude to choose files instead");
+    }
+    let mut tree = FileTree::new(&files, &common_directory(directories));
+    let total_size = tree.nodes[0].size;
+    let mut cursor = 0;
+    let mut scroll = 0;
+    let mut drawn = 0;
+
+    term.hide_cursor()?;
+    let result = loop {
+        let rows = tree.rows();
+        cursor = cursor.min(rows.len().saturating_sub(1));
+        let height = (term.size().0 as usize).saturating_sub(4).max(5);
+        if cursor < scroll {
+            scroll = cursor;
+        } else if cursor >= scroll + height {
+            scroll = cursor + 1 - height;
+        }
+
+        let mut lines = vec![format!(
+            "{} {}",
+            style("Select files to download:").bold(),
+            style("(↑/↓ move, →/← expand/collapse, space toggle, a all, enter download, esc cancel)").dim()
+        )];
+        for (position, &node) in rows.iter().enumerate().skip(scroll).take(height) {
+            let entry = &tree.nodes[node];
+            let checkbox = match tree.selection(node) {
+                Selection::All => style("[x]").green(),
+                Selection::None => style("[ ]").dim(),
+                Selection::Partial => style("[-]").yellow(),
+            };
+            let (marker, name) = match entry.file {
+                Some(_) => (" ", style(entry.name.clone())),
+                None if entry.expanded => ("▾", style(format!("{}/", entry.name)).cyan()),
+                None => ("▸", style(format!("{}/", entry.name)).cyan()),
+            };
+            let pointer = if position == cursor { style(">").cyan().bold() } else { style(" ") };
+            lines.push(format!(
+                "{} {}{} {} {} {}",
+                pointer,
+                "  ".repeat(entry.depth),
+                marker,
+                checkbox,
+                name,
+                style(format!("({})", format_size(entry.size))).dim()
+            ));
+        }
+        lines.push(format!(
+            "{} {} of {} files, {} of {}",
+            style("Selected:").bold(),
+            style(tree.selected_count()).cyan().bold(),
+            files.len(),
+            style(format_size(tree.selected_size())).cyan().bold(),
+            format_size(total_size)
+        ));
+        term.clear_last_lines(drawn)?;
+        for line in &lines {
+            term.write_line(line)?;
+        }
+        drawn = lines.len();
+
+        let Some(&node) = rows.get(cursor) else {
+            break None;
+        };
+        match term.read_key()? {
+            Key::ArrowUp | Key::Char('k') => cursor = cursor.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') => cursor = (cursor + 1).min(rows.len() - 1),
+            Key::Home => cursor = 0,
+            Key::End => cursor = rows.len() - 1,
+            Key::ArrowRight | Key::Char('l') if tree.nodes[node].file.is_none() => {
+                tree.nodes[node].expanded = true;
+            }
+            Key::ArrowLeft | Key::Char('h') => {
+                if tree.nodes[node].file.is_none() && tree.nodes[node].expanded {
+                    tree.nodes[node].expanded = false;
+                } else if let Some(parent) = tree.parent(node) {
+                    tree.nodes[parent].expanded = false;
+                    cursor = rows.iter().position(|&row| row == parent).unwrap_or(cursor);
+                }
+            }
+            Key::Char(' ') => tree.toggle(node),
+            Key::Char('a') => tree.toggle(0),
+            Key::Enter => break Some(tree.selected.clone()),
+            Key::Escape | Key::Char('q') => break None,
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn)?;
+    term.show_cursor()?;
+
+    Ok(result.map(|selected| {
+        files
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(file, selected)| selected.then_some(file))
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggling_directories_and_files() {
+        let files = vec![
+            GitHubFile::fixture("api/README.md", "", 10),
+            GitHubFile::fixture("api/v1/a.proto", "", 100),
+            GitHubFile::fixture("api/v1/b.proto", "", 200),
+            GitHubFile::fixture("api/v1/gen/c.rs", "", 1000),
+        ];
+        let mut tree = FileTree::new(&files, "api");
+        let names = |tree: &FileTree| tree.rows().iter().map(|&n| tree.nodes[n].name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&tree), ["README.md", "v1"]);
+        assert_eq!(tree.nodes[0].size, 1310);
+
+        let v1 = tree.rows()[1];
+        assert_eq!(tree.nodes[v1].size, 1300);
+        tree.nodes[v1].expanded = true;
+        assert_eq!(names(&tree), ["README.md", "v1", "a.proto", "b.proto", "gen"]);
+
+        tree.toggle(v1);
+        assert_eq!((tree.selected_count(), tree.selected_size()), (1, 10));
+        assert_eq!(tree.selection(0), Selection::Partial);
+        let b = tree.rows()[3];
+        tree.toggle(b);
+        assert_eq!(tree.selection(v1), Selection::Partial);
+        assert_eq!(tree.selected, [true, false, true, false]);
+        assert_eq!(tree.parent(b), Some(v1));
+
+        tree.toggle(0);
+        assert_eq!(tree.selection(0), Selection::All);
+
+        let directories = ["api/v1".to_string(), "api/v2/x".to_string(), "api/LICENSE".to_string()];
+        assert_eq!(common_directory(&directories), "api");
+        assert_eq!(common_directory(&directories[..1]), "api/v1");
+    }
+}
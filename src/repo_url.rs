@@ -0,0 +1,427 @@
+use crate::github::{RepoInfoError, RepositoryInfo};
+use url::Url;
+
+const WEB_HOSTS: &[&str] = &["github.com", "www.github.com"];
+const RAW_HOST: &str = "raw.githubusercontent.com";
+const API_HOST: &str = "api.github.com";
+const GITHUB_API_BASE: &str = "https://api.github.com";
+
+/// Where a parsed URL points: github.com or a GitHub Enterprise Server host.
+struct Server {
+    host: String,
+    api_base: String,
+}
+
+impl Server {
+    fn github() -> Self {
+        Server {
+            host: "github.com".to_string(),
+            api_base: GITHUB_API_BASE.to_string(),
+        }
+    }
+
+    /// GHES serves its REST API under `/api/v3` on the same host.
+    fn enterprise(scheme: &str, host: &str) -> Self {
+        Server {
+            host: host.to_string(),
+            api_base: format!("{}://{}/api/v3", scheme, host),
+        }
+    }
+}
+
+/// Normalizes a configured enterprise host (`ghe.example.com`, or a URL such as
+/// `https://ghe.example.com/api/v3`) to the `host[:port]` form URLs are matched on.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let without_scheme = host.split_once("://").map_or(host, |(_, rest)| rest);
+    without_scheme
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn authority(parsed_url: &Url) -> Option<String> {
+    let host = parsed_url.host_str()?.to_ascii_lowercase();
+    Some(match parsed_url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    })
+}
+
+/// Normalizes every way we accept to point at a repository into a `RepositoryInfo`:
+///
+/// - web URLs (`https://github.com/owner/repo/tree/<ref>/<path>`, `/blob/...`)
+/// - raw file URLs (`https://raw.githubusercontent.com/owner/repo/<ref>/<path>`)
+/// - API URLs (`https://api.github.com/repos/owner/repo/contents/<path>?ref=<ref>`)
+/// - SSH remotes (`git@github.com:owner/repo.git`, `ssh://git@github.com/owner/repo`)
+/// - the `owner/repo[/path][@ref]` shorthand
+///
+/// Query strings and fragments such as `?plain=1` or `#L10` are ignored, and a
+/// trailing `.git` on the repository name is dropped.
+///
+/// The same shapes are accepted for the GitHub Enterprise Server hosts listed in
+/// `enterprise_hosts`, whose raw and API URLs live under `/raw/` and `/api/v3/`.
+pub fn parse_repository_url(
+    input: &str,
+    enterprise_hosts: &[String],
+) -> Result<RepositoryInfo, RepoInfoError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(RepoInfoError::InvalidUrl("URL is empty".to_string()));
+    }
+
+    if let Some(rest) = input.strip_prefix("git@") {
+        return parse_scp_remote(rest, enterprise_hosts);
+    }
+
+    if input.contains("://") {
+        let parsed_url = Url::parse(input).map_err(|e| RepoInfoError::InvalidUrl(e.to_string()))?;
+        return parse_url(&parsed_url, enterprise_hosts);
+    }
+
+    // `github.com/owner/repo` without a scheme is a URL, anything else is shorthand.
+    let first = input.split('/').next().unwrap_or_default();
+    if first.contains('.') || first.contains(':') {
+        let parsed_url = Url::parse(&format!("https://{}", input))
+            .map_err(|e| RepoInfoError::InvalidUrl(e.to_string()))?;
+        return parse_url(&parsed_url, enterprise_hosts);
+    }
+
+    parse_shorthand(input)
+}
+
+fn parse_url(parsed_url: &Url, enterprise_hosts: &[String]) -> Result<RepositoryInfo, RepoInfoError> {
+    let host = authority(parsed_url)
+        .ok_or_else(|| RepoInfoError::InvalidUrl("URL has no host".to_string()))?;
+    let is_enterprise = enterprise_hosts.contains(&host);
+
+    let segments: Vec<&str> = parsed_url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    match parsed_url.scheme() {
+        "http" | "https" => {}
+        "ssh" | "git" if WEB_HOSTS.contains(&host.as_str()) => {
+            return repository_root(Server::github(), &segments);
+        }
+        "ssh" | "git" if is_enterprise => {
+            return repository_root(Server::enterprise("https", &host), &segments);
+        }
+        scheme => return Err(RepoInfoError::UnsupportedScheme(scheme.to_string())),
+    }
+
+    if WEB_HOSTS.contains(&host.as_str()) {
+        parse_web_path(Server::github(), &segments)
+    } else if host == RAW_HOST {
+        parse_raw_path(Server::github(), &segments)
+    } else if host == API_HOST {
+        parse_api_path(Server::github(), &segments, parsed_url)
+    } else if is_enterprise {
+        let server = Server::enterprise(parsed_url.scheme(), &host);
+        match segments.as_slice() {
+            ["api", "v3", rest @ ..] => parse_api_path(server, rest, parsed_url),
+            ["raw", rest @ ..] => parse_raw_path(server, rest),
+            _ => parse_web_path(server, &segments),
+        }
+    } else if let Some(enterprise_host) = host
+        .strip_prefix("raw.")
+        .filter(|h| enterprise_hosts.iter().any(|e| e == h))
+    {
+        // GHES with subdomain isolation serves raw files from `raw.<host>`.
+        parse_raw_path(Server::enterprise(parsed_url.scheme(), enterprise_host), &segments)
+    } else {
+        Err(RepoInfoError::UnsupportedHost(host))
+    }
+}
+
+fn new_info(server: Server, user: &str, repository: &str) -> RepositoryInfo {
+    RepositoryInfo {
+        host: server.host,
+        api_base: server.api_base,
+        user: user.to_string(),
+        repository: repository.trim_end_matches(".git").to_string(),
+        git_reference: None,
+        directory: String::new(),
+        reference_in_path: false,
+        pull_request: None,
+        resolved_ref: None,
+    }
+}
+
+fn owner_and_repo<'a>(segments: &[&'a str]) -> Result<(&'a str, &'a str), RepoInfoError> {
+    match segments {
+        [user, repository, ..] if !repository.trim_end_matches(".git").is_empty() => {
+            Ok((user, repository))
+        }
+        _ => Err(RepoInfoError::NotARepository(
+            "URL path does not contain user and repository".to_string(),
+        )),
+    }
+}
+
+fn repository_root(server: Server, segments: &[&str]) -> Result<RepositoryInfo, RepoInfoError> {
+    let (user, repository) = owner_and_repo(segments)?;
+    if segments.len() > 2 {
+        return Err(RepoInfoError::UnsupportedPath(segments[2..].join("/")));
+    }
+    Ok(new_info(server, user, repository))
+}
+
+/// `git@github.com:owner/repo.git`
+fn parse_scp_remote(rest: &str, enterprise_hosts: &[String]) -> Result<RepositoryInfo, RepoInfoError> {
+    let (host, path) = rest
+        .split_once(':')
+        .ok_or_else(|| RepoInfoError::InvalidUrl(format!("git@{}", rest)))?;
+    let host = host.to_ascii_lowercase();
+    let server = if WEB_HOSTS.contains(&host.as_str()) {
+        Server::github()
+    } else if enterprise_hosts.contains(&host) {
+        Server::enterprise("https", &host)
+    } else {
+        return Err(RepoInfoError::UnsupportedHost(host));
+    };
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    repository_root(server, &segments)
+}
+
+/// `github.com/owner/repo[/tree|blob/<ref>/<path>]`, plus pull request
+/// (`/pull/<n>`), commit (`/commit/<sha>`) and release (`/releases/tag/<tag>`) pages.
+fn parse_web_path(server: Server, segments: &[&str]) -> Result<RepositoryInfo, RepoInfoError> {
+    let (user, repository) = owner_and_repo(segments)?;
+    let mut info = new_info(server, user, repository);
+    let missing_reference = || RepoInfoError::MissingReference(segments.join("/"));
+
+    match segments[2..] {
+        [] => {}
+        ["tree" | "blob", ref rest @ ..] => {
+            let reference = rest.first().ok_or_else(missing_reference)?;
+            info.git_reference = Some(reference.to_string());
+            info.directory = rest[1..].join("/");
+            info.reference_in_path = true;
+        }
+        ["pull" | "pulls", number, ..] => {
+            let number = number
+                .parse()
+                .map_err(|_| RepoInfoError::InvalidPullRequest(number.to_string()))?;
+            info.pull_request = Some(number);
+        }
+        ["pull" | "pulls"] => return Err(RepoInfoError::InvalidPullRequest(String::new())),
+        ["commit" | "commits", sha, ..] => info.git_reference = Some(sha.to_string()),
+        ["commit" | "commits"] => return Err(missing_reference()),
+        ["releases", "tag", ref tag @ ..] if !tag.is_empty() => {
+            info.git_reference = Some(tag.join("/"));
+        }
+        _ => return Err(RepoInfoError::UnsupportedPath(segments[2..].join("/"))),
+    }
+
+    Ok(info)
+}
+
+/// `raw.githubusercontent.com/owner/repo/<ref>/<path>` (`<host>/raw/owner/repo/...`
+/// on GHES), where the ref may also be
+/// spelled out as `refs/heads/<branch>` or `refs/tags/<tag>`.
+fn parse_raw_path(server: Server, segments: &[&str]) -> Result<RepositoryInfo, RepoInfoError> {
+    let (user, repository) = owner_and_repo(segments)?;
+    let mut info = new_info(server, user, repository);
+
+    let rest = match segments[2..] {
+        ["refs", "heads" | "tags", ref rest @ ..] => rest,
+        ref rest => rest,
+    };
+    match rest {
+        [] => return Err(RepoInfoError::MissingReference(segments.join("/"))),
+        [_] => return Err(RepoInfoError::MissingPath(segments.join("/"))),
+        [reference, path @ ..] => {
+            info.git_reference = Some(reference.to_string());
+            info.directory = path.join("/");
+            info.reference_in_path = true;
+        }
+    }
+
+    Ok(info)
+}
+
+/// `api.github.com/repos/owner/repo[/contents/<path>][?ref=<ref>]`, or the same
+/// path under `<host>/api/v3/` on GHES.
+fn parse_api_path(
+    server: Server,
+    segments: &[&str],
+    parsed_url: &Url,
+) -> Result<RepositoryInfo, RepoInfoError> {
+    let segments = match segments {
+        ["repos", rest @ ..] => rest,
+        _ => return Err(RepoInfoError::UnsupportedPath(segments.join("/"))),
+    };
+    let (user, repository) = owner_and_repo(segments)?;
+    let mut info = new_info(server, user, repository);
+
+    match segments.get(2) {
+        None => {}
+        Some(&"contents") => info.directory = segments[3..].join("/"),
+        Some(&"pulls") => {
+            let number = segments.get(3).copied().unwrap_or_default();
+            info.pull_request = Some(
+                number
+                    .parse()
+                    .map_err(|_| RepoInfoError::InvalidPullRequest(number.to_string()))?,
+            );
+        }
+        Some(_) => return Err(RepoInfoError::UnsupportedPath(segments[2..].join("/"))),
+    }
+    info.git_reference = parsed_url
+        .query_pairs()
+        .find(|(key, _)| key == "ref")
+        .map(|(_, value)| value.into_owned())
+        .filter(|value| !value.is_empty());
+
+    Ok(info)
+}
+
+/// `owner/repo[/path][@ref]`
+fn parse_shorthand(input: &str) -> Result<RepositoryInfo, RepoInfoError> {
+    let (location, reference) = match input.split_once('@') {
+        Some((_, "")) => return Err(RepoInfoError::MissingReference(input.to_string())),
+        Some((location, reference)) => (location, Some(reference)),
+        None => (input, None),
+    };
+
+    let segments: Vec<&str> = location.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.len() < 2 || segments[..2].iter().any(|s| s.contains(['?', '#', ':'])) {
+        return Err(RepoInfoError::InvalidShorthand(input.to_string()));
+    }
+
+    let mut info = new_info(Server::github(), segments[0], segments[1]);
+    info.directory = segments[2..].join("/");
+    info.git_reference = reference.map(str::to_string);
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Expected = (&'static str, &'static str, Option<&'static str>, &'static str, bool);
+    type ErrorCheck = fn(&RepoInfoError) -> bool;
+
+    #[test]
+    fn test_accepted_url_shapes() {
+        let cases: &[(&str, Expected)] = &[
+            ("https://github.com/user/repo", ("user", "repo", None, "", false)),
+            ("https://www.github.com/user/repo.git", ("user", "repo", None, "", false)),
+            ("http://github.com/user/repo/", ("user", "repo", None, "", false)),
+            ("github.com/user/repo/tree/main/src", ("user", "repo", Some("main"), "src", true)),
+            (
+                "https://github.com/user/repo/tree/main/src/lib?plain=1#readme",
+                ("user", "repo", Some("main"), "src/lib", true),
+            ),
+            (
+                "https://github.com/user/repo/blob/v1.2/src/main.rs#L10-L20",
+                ("user", "repo", Some("v1.2"), "src/main.rs", true),
+            ),
+            (
+                "https://raw.githubusercontent.com/user/repo/main/docs/guide.md",
+                ("user", "repo", Some("main"), "docs/guide.md", true),
+            ),
+            (
+                "https://raw.githubusercontent.com/user/repo/refs/heads/team/topic/a.txt",
+                ("user", "repo", Some("team"), "topic/a.txt", true),
+            ),
+            (
+                "https://api.github.com/repos/user/repo/contents/src/bin?ref=feature/x",
+                ("user", "repo", Some("feature/x"), "src/bin", false),
+            ),
+            ("https://api.github.com/repos/user/repo", ("user", "repo", None, "", false)),
+            ("git@github.com:user/repo.git", ("user", "repo", None, "", false)),
+            ("ssh://git@github.com/user/repo.git", ("user", "repo", None, "", false)),
+            ("user/repo", ("user", "repo", None, "", false)),
+            ("user/repo/proto/v1", ("user", "repo", None, "proto/v1", false)),
+            ("user/repo/proto@team/topic", ("user", "repo", Some("team/topic"), "proto", false)),
+            ("user/repo@v2.0.0", ("user", "repo", Some("v2.0.0"), "", false)),
+            (
+                "https://github.com/user/repo/commit/1a2b3c4d#diff-123",
+                ("user", "repo", Some("1a2b3c4d"), "", false),
+            ),
+            (
+                "https://github.com/user/repo/releases/tag/release/v1.0",
+                ("user", "repo", Some("release/v1.0"), "", false),
+            ),
+        ];
+
+        for (input, (user, repository, reference, directory, in_path)) in cases {
+            let info = parse_repository_url(input, &[]).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(info.user, *user, "{}", input);
+            assert_eq!(info.repository, *repository, "{}", input);
+            assert_eq!(info.git_reference.as_deref(), *reference, "{}", input);
+            assert_eq!(info.directory, *directory, "{}", input);
+            assert_eq!(info.reference_in_path, *in_path, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_rejected_url_shapes() {
+        let cases: &[(&str, ErrorCheck)] = &[
+            ("", |e| matches!(e, RepoInfoError::InvalidUrl(_))),
+            ("https://gitlab.com/user/repo", |e| matches!(e, RepoInfoError::UnsupportedHost(_))),
+            ("git@gitlab.com:user/repo.git", |e| matches!(e, RepoInfoError::UnsupportedHost(_))),
+            ("ftp://github.com/user/repo", |e| matches!(e, RepoInfoError::UnsupportedScheme(_))),
+            ("https://github.com/user", |e| matches!(e, RepoInfoError::NotARepository(_))),
+            ("https://github.com/user/repo/issues/4", |e| matches!(e, RepoInfoError::UnsupportedPath(_))),
+            ("https://github.com/user/repo/tree", |e| matches!(e, RepoInfoError::MissingReference(_))),
+            ("https://github.com/user/repo/pull/abc", |e| matches!(e, RepoInfoError::InvalidPullRequest(_))),
+            ("https://github.com/user/repo/commit", |e| matches!(e, RepoInfoError::MissingReference(_))),
+            ("https://raw.githubusercontent.com/user/repo/main", |e| matches!(e, RepoInfoError::MissingPath(_))),
+            ("https://api.github.com/users/user", |e| matches!(e, RepoInfoError::UnsupportedPath(_))),
+            ("user/repo@", |e| matches!(e, RepoInfoError::MissingReference(_))),
+            ("justone", |e| matches!(e, RepoInfoError::InvalidShorthand(_))),
+        ];
+
+        for (input, is_expected) in cases {
+            match parse_repository_url(input, &[]) {
+                Ok(info) => panic!("{} should be rejected, got {:?}", input, info),
+                Err(e) => assert!(is_expected(&e), "{}: unexpected error {:?}", input, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pull_request_urls() {
+        for input in [
+            "https://github.com/user/repo/pull/123",
+            "https://github.com/user/repo/pull/123/files",
+            "https://api.github.com/repos/user/repo/pulls/123",
+        ] {
+            let info = parse_repository_url(input, &[]).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(info.pull_request, Some(123), "{}", input);
+            assert_eq!(info.git_reference, None, "{}", input);
+            assert_eq!(info.directory, "", "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_enterprise_hosts() {
+        let hosts = vec![normalize_host("https://GHE.example.com/api/v3"), "localhost:8080".to_string()];
+        let cases: &[(&str, &str, &str, &str)] = &[
+            ("https://ghe.example.com/team/repo/tree/main/src", "ghe.example.com", "https://ghe.example.com/api/v3", "src"),
+            ("https://ghe.example.com/raw/team/repo/main/a.txt", "ghe.example.com", "https://ghe.example.com/api/v3", "a.txt"),
+            ("https://raw.ghe.example.com/team/repo/main/a.txt", "ghe.example.com", "https://ghe.example.com/api/v3", "a.txt"),
+            ("https://ghe.example.com/api/v3/repos/team/repo/contents/lib", "ghe.example.com", "https://ghe.example.com/api/v3", "lib"),
+            ("git@ghe.example.com:team/repo.git", "ghe.example.com", "https://ghe.example.com/api/v3", ""),
+            ("http://localhost:8080/team/repo/tree/main/x", "localhost:8080", "http://localhost:8080/api/v3", "x"),
+            ("https://github.com/team/repo", "github.com", "https://api.github.com", ""),
+        ];
+
+        for (input, host, api_base, directory) in cases {
+            let info = parse_repository_url(input, &hosts).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(info.host, *host, "{}", input);
+            assert_eq!(info.api_base, *api_base, "{}", input);
+            assert_eq!(info.directory, *directory, "{}", input);
+        }
+
+        assert!(matches!(
+            parse_repository_url("https://other.example.com/team/repo", &hosts),
+            Err(RepoInfoError::UnsupportedHost(_))
+        ));
+    }
+}